@@ -1,315 +1,3795 @@
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
 use jfeed::{Author, Feed, Item};
+use rayon::prelude::*;
+use serde_json::{json, Value};
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
 use std::env;
+use std::ffi::OsString;
+use std::fmt;
 use std::fs;
 use std::fs::File;
 use std::io;
-use std::io::BufRead;
+use std::io::BufWriter;
+use std::io::Read;
 use std::io::Write;
+use std::path::{Path, PathBuf};
 use std::process;
-use time::format_description::well_known;
-use time::{OffsetDateTime, UtcOffset};
+use std::time::Duration;
+use time::format_description::{self, well_known};
+use time::{Date, OffsetDateTime, PrimitiveDateTime, UtcOffset};
+use url::{form_urlencoded, Url};
 
 const VERSION: &str = env!("CARGO_PKG_VERSION");
 const PROGRAM: &str = env!("CARGO_PKG_NAME");
+const REPOSITORY: &str = env!("CARGO_PKG_REPOSITORY");
 
-fn now() -> String {
-    let current_time = OffsetDateTime::now_utc();
-    current_time.format(&well_known::Rfc3339).unwrap()
+const JSON_FEED_VERSIONS: &[&str] = &[
+    "https://jsonfeed.org/version/1",
+    "https://jsonfeed.org/version/1.1",
+];
+
+const EXTENSIONS_NAMESPACE: &str = "https://jsonfeed.org/version/1.1#extensions";
+
+const ITEM_CAPACITY: usize = 512;
+const ITEM_CHUNK: usize = 1024;
+
+const CACHE_HEADERS: &[(&str, &str)] = &[
+    ("ETag", "If-None-Match"),
+    ("Last-Modified", "If-Modified-Since"),
+];
+
+const FLAGS: &[(&str, &str)] = &[
+    ("-h, --help", "show this help and exit"),
+    ("    --version", "show version information and exit"),
+    ("    --stdout", "write to stdout (same as an output of -)"),
+    (
+        "    --format <atom|jsonfeed>",
+        "output format (jsonfeed: normalized JSON Feed 1.1)",
+    ),
+    (
+        "-n, --dry-run",
+        "report whether the output would be written, without writing",
+    ),
+    (
+        "-f, --force",
+        "rewrite file even if modification time is newer than the feed",
+    ),
+    (
+        "    --pretty",
+        "indent nested elements by two spaces per level",
+    ),
+    ("    --minify", "omit the newlines between elements"),
+    (
+        "    --no-trailing-newline",
+        "do not end the output with a newline",
+    ),
+    ("    --eol <lf|crlf>", "line ending to use (default: lf)"),
+    (
+        "    --gzip",
+        "decompress the input even if it does not look gzipped",
+    ),
+    (
+        "    --http-cache",
+        "remember ETag/Last-Modified in output.http-cache, skip unchanged URLs",
+    ),
+    (
+        "    --gzip-output",
+        "compress the output (always done for outputs ending in .gz)",
+    ),
+    (
+        "    --probe-enclosures",
+        "ask the server for the length of enclosures without size_in_bytes",
+    ),
+    (
+        "    --encoding <label>",
+        "character encoding of the input (default: UTF-8, else windows-1252)",
+    ),
+    (
+        "    --newer-than <path>",
+        "rewrite the output only if path is newer than it",
+    ),
+    (
+        "    --skip-unchanged",
+        "do not rewrite an output that only differs in generated dates",
+    ),
+    (
+        "    --also-rss <path>",
+        "also write the feed as RSS 2.0 to path",
+    ),
+    (
+        "    --jobs <n>",
+        "serialize entries on n threads (default 1)",
+    ),
+    (
+        "    --page-size <n>",
+        "split the feed into linked pages of n items (needs --page-prefix)",
+    ),
+    (
+        "    --page-prefix <path>",
+        "write pages to path-1.atom, path-2.atom and so on",
+    ),
+    (
+        "    --outdir <dir>",
+        "convert every input into dir, naming outputs after the inputs",
+    ),
+    (
+        "    --validate",
+        "fail if the generated Atom is not well-formed XML",
+    ),
+    ("    --strict", "treat warnings about the feed as errors"),
+    (
+        "    --pretty-json-error",
+        "show where in the JSON a parse error is",
+    ),
+    (
+        "    --lenient-json",
+        "allow comments and trailing commas in the input",
+    ),
+    (
+        "    --schema-check",
+        "warn about missing required and recommended JSON Feed fields",
+    ),
+    ("-q, --quiet", "do not print informational messages"),
+    ("-v, --verbose", "describe the converted feed on stderr"),
+    (
+        "    --merge",
+        "read one feed per line and merge their items by date",
+    ),
+    (
+        "    --sort <asc|desc>",
+        "sort items by date, undated items last",
+    ),
+    (
+        "    --max-age <age>",
+        "drop items older than age, e.g. 12h, 30d or 2w",
+    ),
+    ("    --drop-undated", "drop items without a valid date"),
+    (
+        "    --limit <n>",
+        "keep only the first n items after sorting (0: all)",
+    ),
+    ("    --title <text>", "use text as the feed title"),
+    (
+        "    --stylesheet <url>",
+        "add an XSLT stylesheet for browsers to render the feed with",
+    ),
+    ("    --no-generator", "omit the <generator> element"),
+    (
+        "    --inherit-authors",
+        "repeat the feed authors in entries without authors",
+    ),
+    (
+        "    --default-author <name>",
+        "author name to use when the feed has no authors",
+    ),
+    (
+        "    --title-as-author",
+        "use the feed title when the feed has no authors",
+    ),
+    (
+        "    --prefer <text|html>",
+        "content to use when an item has both (default: text)",
+    ),
+    (
+        "    --autosummary <n>",
+        "summarize items without a summary in up to n characters",
+    ),
+    (
+        "    --sanitize-html",
+        "remove scripts and other unsafe markup from HTML content",
+    ),
+    (
+        "    --content-wrapper <html>",
+        "wrap HTML content in a template, replacing {{content}}",
+    ),
+    (
+        "    --content-mode <mode>",
+        "wrap HTML content as cdata (default), escaped or xhtml",
+    ),
+    (
+        "    --content-encoded",
+        "also put HTML content into <content:encoded> for RSS tools",
+    ),
+    (
+        "    --lang-on-text",
+        "put an entry's xml:lang on its title, summary and content",
+    ),
+    (
+        "    --keep-extensions",
+        "convert _name extension objects into ext: elements",
+    ),
+    (
+        "    --collapse-whitespace",
+        "join lines and runs of spaces in titles and summaries",
+    ),
+    (
+        "    --preserve-whitespace",
+        "mark text content with xml:space=\"preserve\"",
+    ),
+    (
+        "    --item-link-rel <rel>",
+        "rel of the entry link (default: alternate, empty: none)",
+    ),
+    (
+        "    --no-link-types",
+        "omit type=\"text/html\" from alternate links",
+    ),
+    ("    --rights <text>", "copyright statement for the feed"),
+    (
+        "    --rights-type <text|html>",
+        "whether --rights is plain text (default) or HTML",
+    ),
+    (
+        "    --rights-per-entry",
+        "repeat the --rights statement in every entry",
+    ),
+    (
+        "    --first-url <url>",
+        "link to the first page of a paged feed (rel=\"first\")",
+    ),
+    (
+        "    --prev-url <url>",
+        "link to the previous page of a paged feed (rel=\"previous\")",
+    ),
+    (
+        "    --ns <prefix=uri>",
+        "declare an extra namespace on <feed> (repeatable)",
+    ),
+    (
+        "    --base <url>",
+        "base URL for resolving relative links (xml:base)",
+    ),
+    (
+        "    --feed-id <iri>",
+        "permanent feed id (default: feed_url, else home_page_url)",
+    ),
+    (
+        "    --tag-item-ids",
+        "turn item ids that are not IRIs into tag URIs",
+    ),
+    (
+        "    --tag-date <date>",
+        "date for --tag-item-ids (default: each item's date_published)",
+    ),
+    (
+        "    --published-from-modified",
+        "use date_modified as <published> for items without date_published",
+    ),
+    (
+        "    --now <date>",
+        "time to use for missing dates (default: SOURCE_DATE_EPOCH or now)",
+    ),
+];
+
+#[derive(Clone, Copy, PartialEq)]
+enum ContentMode {
+    Cdata,
+    Escaped,
+    Xhtml,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum OutputFormat {
+    Atom,
+    JsonFeed,
+}
+
+impl OutputFormat {
+    fn extension(self) -> &'static str {
+        match self {
+            OutputFormat::Atom => "atom",
+            OutputFormat::JsonFeed => "json",
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum SortOrder {
+    Ascending,
+    Descending,
+}
+
+#[derive(Clone)]
+struct Options {
+    stdout: bool,
+    dry_run: bool,
+    format: OutputFormat,
+    force: bool,
+    pretty: bool,
+    minify: bool,
+    trailing_newline: bool,
+    crlf: bool,
+    gzip: bool,
+    gzip_output: bool,
+    http_cache: bool,
+    probe_enclosures: bool,
+    encoding: Option<&'static encoding_rs::Encoding>,
+    validate: bool,
+    strict: bool,
+    lenient_json: bool,
+    schema_check: bool,
+    pretty_json_error: bool,
+    quiet: bool,
+    verbose: bool,
+    merge: bool,
+    sort: Option<SortOrder>,
+    max_age: Option<time::Duration>,
+    drop_undated: bool,
+    limit: usize,
+    newer_than: Option<String>,
+    skip_unchanged: bool,
+    also_rss: Option<String>,
+    jobs: usize,
+    page_size: usize,
+    page_prefix: Option<String>,
+    outdir: Option<String>,
+    title: Option<String>,
+    stylesheet: Option<String>,
+    generator: bool,
+    inherit_authors: bool,
+    default_author: Option<String>,
+    title_as_author: bool,
+    prefer_html: bool,
+    autosummary: Option<usize>,
+    sanitize_html: bool,
+    content_wrapper: Option<String>,
+    content_mode: ContentMode,
+    content_encoded: bool,
+    preserve_whitespace: bool,
+    collapse_whitespace: bool,
+    lang_on_text: bool,
+    keep_extensions: bool,
+    item_link_rel: String,
+    link_types: bool,
+    rights: Option<String>,
+    rights_html: bool,
+    rights_per_entry: bool,
+    first_url: Option<String>,
+    prev_url: Option<String>,
+    // Set for each page by --page-prefix; there is no flag for it.
+    self_url: Option<String>,
+    namespaces: Vec<(String, String)>,
+    base: Option<String>,
+    feed_id: Option<String>,
+    tag_item_ids: bool,
+    tag_date: Option<String>,
+    published_from_modified: bool,
+    now: OffsetDateTime,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            stdout: false,
+            dry_run: false,
+            format: OutputFormat::Atom,
+            force: false,
+            pretty: false,
+            minify: false,
+            trailing_newline: true,
+            crlf: false,
+            gzip: false,
+            gzip_output: false,
+            http_cache: false,
+            probe_enclosures: false,
+            encoding: None,
+            validate: false,
+            strict: false,
+            lenient_json: false,
+            schema_check: false,
+            pretty_json_error: false,
+            quiet: false,
+            verbose: false,
+            merge: false,
+            sort: None,
+            max_age: None,
+            drop_undated: false,
+            limit: 0,
+            newer_than: None,
+            skip_unchanged: false,
+            also_rss: None,
+            jobs: 1,
+            page_size: 0,
+            page_prefix: None,
+            outdir: None,
+            title: None,
+            stylesheet: None,
+            generator: true,
+            inherit_authors: false,
+            default_author: None,
+            title_as_author: false,
+            prefer_html: false,
+            autosummary: None,
+            sanitize_html: false,
+            content_wrapper: None,
+            content_mode: ContentMode::Cdata,
+            content_encoded: false,
+            preserve_whitespace: false,
+            collapse_whitespace: false,
+            lang_on_text: false,
+            keep_extensions: false,
+            item_link_rel: "alternate".to_string(),
+            link_types: true,
+            rights: None,
+            rights_html: false,
+            rights_per_entry: false,
+            first_url: None,
+            prev_url: None,
+            self_url: None,
+            namespaces: Vec::new(),
+            base: None,
+            feed_id: None,
+            tag_item_ids: false,
+            tag_date: None,
+            published_from_modified: false,
+            // Whole seconds, so undated entries get the same form as dated ones.
+            now: OffsetDateTime::now_utc().replace_nanosecond(0).unwrap(),
+        }
+    }
+}
+
+struct Context<'a> {
+    options: &'a Options,
+    feed: Option<&'a Feed>,
+    raw: &'a [Value],
+    raw_items: &'a BTreeMap<&'a str, &'a Value>,
+    sources: &'a BTreeMap<String, Option<Source>>,
+}
+
+#[derive(Clone)]
+struct Source {
+    id: Option<String>,
+    title: String,
+    home_page_url: Option<String>,
+}
+
+fn parse_date(date: &str) -> Option<OffsetDateTime> {
+    OffsetDateTime::parse(date, &well_known::Rfc3339).ok()
+}
+
+// RFC 3339 has four-digit years, so other dates could not be written out.
+fn has_rfc3339_year(date: OffsetDateTime) -> bool {
+    (0..=9999).contains(&date.year())
+}
+
+fn parse_loose_date(date: &str) -> Option<OffsetDateTime> {
+    // The formats below accept signed years such as -0001.
+    guess_date(date).filter(|date| has_rfc3339_year(*date))
+}
+
+fn guess_date(date: &str) -> Option<OffsetDateTime> {
+    let date = date.trim();
+
+    if let Ok(date) = OffsetDateTime::parse(date, &well_known::Rfc2822) {
+        return Some(date);
+    }
+
+    // Dates like "2023-01-02 15:04:05", with or without an offset. Without one, assume UTC.
+    let date = date.replacen(' ', "T", 1);
+    if let Some(date) = parse_date(&date) {
+        return Some(date);
+    }
+
+    let format = format_description::parse("[year]-[month]-[day]T[hour]:[minute]:[second]").ok()?;
+    if let Ok(date) = PrimitiveDateTime::parse(&date, &format) {
+        return Some(date.assume_utc());
+    }
+
+    let format = format_description::parse("[year]-[month]-[day]").ok()?;
+    Date::parse(&date, &format)
+        .ok()
+        .map(|date| date.midnight().assume_utc())
+}
+
+fn format_date(date: OffsetDateTime) -> String {
+    date.format(&well_known::Rfc3339).unwrap()
 }
 
 fn get_mtime(file: &str) -> Option<OffsetDateTime> {
     if let Ok(metadata) = fs::metadata(file) {
         if let Ok(modified) = metadata.modified() {
-            let mut odt: OffsetDateTime = modified.into();
+            let odt: OffsetDateTime = modified.into();
+
+            // Comparisons are by instant, so staying in UTC is fine when the
+            // local offset cannot be determined (e.g. in multithreaded processes).
             if let Ok(offset) = UtcOffset::local_offset_at(odt) {
-                odt = odt.to_offset(offset);
-                return Some(odt);
+                return Some(odt.to_offset(offset));
             }
+
+            return Some(odt);
         }
     }
 
     None
 }
 
-trait ToAtom {
-    fn to_atom(&self) -> String;
+fn is_xml_char(c: char) -> bool {
+    matches!(
+        c,
+        '\t' | '\n' | '\r' | ' '..='\u{d7ff}' | '\u{e000}'..='\u{fffd}' | '\u{10000}'..='\u{10ffff}'
+    )
+}
 
-    fn updated(&self) -> Option<OffsetDateTime> {
-        None
+fn escape_xml(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+
+    for c in text.chars().filter(|c| is_xml_char(*c)) {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            _ => escaped.push(c),
+        }
     }
+
+    escaped
 }
 
-impl ToAtom for Author {
-    fn to_atom(&self) -> String {
-        let mut output = "<author>\n<name>".to_string();
-        if let Some(name) = &self.name {
-            output += &name;
-        }
-        output += "</name>\n";
+fn escape_cdata(text: &str) -> String {
+    let text: String = text.chars().filter(|c| is_xml_char(*c)).collect();
+    text.replace("]]>", "]]]]><![CDATA[>")
+}
 
-        if let Some(url) = &self.url {
-            output += &format!("<uri>{}</uri>\n", url);
-        }
-        output += "</author>\n";
+const CONTENT_PLACEHOLDER: &str = "{{content}}";
 
-        output
+const VOID_ELEMENTS: &[&str] = &[
+    "area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta", "source", "track",
+    "wbr",
+];
+
+// Applied after sanitizing, so the wrapper's own markup is kept as given.
+fn wrap_content<'a>(content_html: Cow<'a, str>, options: &Options) -> Cow<'a, str> {
+    match &options.content_wrapper {
+        Some(template) => Cow::Owned(template.replace(CONTENT_PLACEHOLDER, &content_html)),
+        None => content_html,
     }
 }
 
-impl ToAtom for Item {
-    fn updated(&self) -> Option<OffsetDateTime> {
-        if let Some(date_modified) = &self.date_modified {
-            return OffsetDateTime::parse(date_modified, &well_known::Rfc3339).ok();
-        }
+fn to_xhtml(html: &str) -> Option<String> {
+    let mut xhtml = String::with_capacity(html.len());
+    let mut rest = html;
 
-        if let Some(date_published) = &self.date_published {
-            return OffsetDateTime::parse(date_published, &well_known::Rfc3339).ok();
-        };
+    while let Some(start) = rest.find('<') {
+        xhtml.push_str(&rest[..start]);
+        rest = &rest[start..];
 
-        None
+        let end = rest.find('>')? + 1;
+        let tag = &rest[..end];
+        let name = tag[1..]
+            .split(|c: char| c.is_whitespace() || c == '/' || c == '>')
+            .next()
+            .unwrap_or("")
+            .to_ascii_lowercase();
+
+        if VOID_ELEMENTS.contains(&name.as_str()) && !tag.ends_with("/>") {
+            xhtml.push_str(&tag[..tag.len() - 1]);
+            xhtml.push_str("/>");
+        } else {
+            xhtml.push_str(tag);
+        }
+
+        rest = &rest[end..];
     }
 
-    fn to_atom(&self) -> String {
-        let mut output = "".to_string();
+    xhtml.push_str(rest);
 
-        if let Some(language) = &self.language {
-            output += &format!("<entry xml:lang=\"{}\">\n", language);
-        } else {
-            output += "<entry>\n";
-        };
+    let xhtml = format!(
+        "<div xmlns=\"http://www.w3.org/1999/xhtml\">{}</div>",
+        xhtml.replace("&nbsp;", "&#160;")
+    );
 
-        output += &format!("<id>{}</id>\n", &self.id);
-        if let Some(title) = &self.title {
-            output += &format!("<title>{}</title>\n", &title);
-        }
+    roxmltree::Document::parse(&xhtml).ok()?;
+    Some(xhtml)
+}
 
-        if let Some(url) = &self.url {
-            output += &format!("<link rel=\"alternate\" href=\"{}\"/>\n", &url);
-        }
+fn token_len(xml: &str) -> usize {
+    let end = if xml.starts_with("<![CDATA[") {
+        xml.find("]]>").map(|i| i + 3)
+    } else if xml.starts_with("<!--") {
+        xml.find("-->").map(|i| i + 3)
+    } else if xml.starts_with('<') {
+        xml.find('>').map(|i| i + 1)
+    } else {
+        xml.find('<')
+    };
 
-        if let Some(summary) = &self.summary {
-            output += &format!("<summary>{}</summary>\n", &summary);
-        }
+    end.unwrap_or(xml.len())
+}
 
-        if let Some(content_text) = &self.content_text {
-            output += &format!("<content type=\"text\">{}</content>\n", &content_text);
-        } else if let Some(content_html) = &self.content_html {
-            output += &format!(
-                "<content type=\"html\"><![CDATA[ {} ]]></content>\n",
-                &content_html
-            );
-        }
+// Whitespace inside these is content, even between tags as in xhtml.
+const TEXT_ELEMENTS: &[&str] = &[
+    "content",
+    "summary",
+    "title",
+    "subtitle",
+    "rights",
+    "description",
+];
 
-        let updated = if let Some(date_modified) = &self.date_modified {
-            date_modified.to_string()
-        } else if let Some(date_published) = &self.date_published {
-            date_published.to_string()
-        } else {
-            now()
-        };
+fn format_xml(xml: &str, newline: &str, indent: &str) -> String {
+    let mut tokens = Vec::new();
+    let mut rest = xml;
 
-        output += &format!("<updated>{}</updated>\n", updated);
+    while !rest.is_empty() {
+        let len = token_len(rest);
+        tokens.push(&rest[..len]);
+        rest = &rest[len..];
+    }
+
+    let mut output = String::with_capacity(xml.len() * 2);
+    let mut depth: usize = 0;
+    // The depth of the text construct being copied as is, if inside one.
+    let mut verbatim = None;
+
+    for (i, token) in tokens.iter().enumerate() {
+        let between_tags = verbatim.is_none()
+            && *token == "\n"
+            && i > 0
+            && tokens[i - 1].starts_with('<')
+            && tokens.get(i + 1).is_some_and(|next| next.starts_with('<'));
+
+        if between_tags {
+            let level = if tokens[i + 1].starts_with("</") {
+                depth.saturating_sub(1)
+            } else {
+                depth
+            };
 
-        if let Some(date_published) = &self.date_published {
-            output += &format!("<published>{}</published>\n", &date_published);
+            output.push_str(newline);
+            output.push_str(&indent.repeat(level));
+            continue;
         }
 
-        if let Some(authors) = &self.authors {
-            for author in authors {
-                output += &author.to_atom();
+        if token.starts_with("</") {
+            depth = depth.saturating_sub(1);
+
+            if verbatim == Some(depth) {
+                verbatim = None;
+            }
+        } else if token.starts_with('<')
+            && !token.starts_with("<?")
+            && !token.starts_with("<!")
+            && !token.ends_with("/>")
+        {
+            let name = token[1..]
+                .split(|c: char| c.is_whitespace() || c == '>')
+                .next()
+                .unwrap_or("");
+
+            if verbatim.is_none() && TEXT_ELEMENTS.contains(&name) {
+                verbatim = Some(depth);
             }
+
+            depth += 1;
         }
 
-        if let Some(attachments) = &self.attachments {
-            for attachment in attachments {
-                output += &format!(
-                    "<link rel=\"enclosure\" href=\"{}\"/ type=\"{}\"",
-                    &attachment.url, &attachment.mime_type
-                );
+        output.push_str(token);
+    }
 
-                if let Some(size_in_bytes) = &attachment.size_in_bytes {
-                    output += &format!(" length=\"{}\"", &size_in_bytes);
-                }
+    output
+}
 
-                output += ">\n";
-            }
+fn strip_tags(html: &str) -> String {
+    let mut text = String::with_capacity(html.len());
+    let mut in_tag = false;
+
+    for c in html.chars() {
+        if c == '<' {
+            in_tag = true;
+        } else if c == '>' && in_tag {
+            in_tag = false;
+            text.push(' ');
+        } else if !in_tag {
+            text.push(c);
         }
+    }
 
-        output += "</entry>\n";
-        output
+    decode_entities(&text)
+}
+
+// The references that commonly show up in blog posts. Others are left as they are.
+const ENTITIES: &[(&str, char)] = &[
+    ("amp", '&'),
+    ("lt", '<'),
+    ("gt", '>'),
+    ("quot", '"'),
+    ("apos", '\''),
+    ("nbsp", ' '),
+    ("hellip", '…'),
+    ("mdash", '—'),
+    ("ndash", '–'),
+    ("lsquo", '‘'),
+    ("rsquo", '’'),
+    ("ldquo", '“'),
+    ("rdquo", '”'),
+    ("laquo", '«'),
+    ("raquo", '»'),
+    ("middot", '·'),
+    ("bull", '•'),
+    ("copy", '©'),
+    ("reg", '®'),
+    ("trade", '™'),
+];
+
+fn decode_entity(name: &str) -> Option<char> {
+    if let Some(number) = name.strip_prefix('#') {
+        let code = match number.strip_prefix(['x', 'X']) {
+            Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+            None => number.parse().ok()?,
+        };
+
+        return char::from_u32(code).filter(|c| *c != '\0');
     }
+
+    ENTITIES
+        .iter()
+        .find(|(entity, _)| *entity == name)
+        .map(|(_, c)| *c)
 }
 
-impl ToAtom for Feed {
-    fn updated(&self) -> Option<OffsetDateTime> {
-        let mut updated = None;
+fn decode_entities(text: &str) -> String {
+    let mut decoded = String::with_capacity(text.len());
+    let mut rest = text;
 
-        if let Some(items) = &self.items {
-            for item in items {
-                if let Some(item_updated) = item.updated() {
-                    if let Some(u) = updated {
-                        if item_updated > u {
-                            updated = Some(item_updated);
-                        }
-                    } else {
-                        updated = Some(item_updated);
-                    }
-                }
+    while let Some(start) = rest.find('&') {
+        decoded.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        let entity = rest[1..]
+            .find(';')
+            .and_then(|end| Some((end, decode_entity(&rest[1..end + 1])?)));
+
+        match entity {
+            Some((end, c)) => {
+                decoded.push(c);
+                rest = &rest[end + 2..];
+            }
+            None => {
+                decoded.push('&');
+                rest = &rest[1..];
             }
         }
+    }
 
-        updated
+    decoded.push_str(rest);
+    decoded
+}
+
+fn collapse_whitespace(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+fn truncate_words(text: &str, max_chars: usize) -> String {
+    let text = collapse_whitespace(text);
+    if text.chars().count() <= max_chars {
+        return text;
     }
 
-    fn to_atom(&self) -> String {
-        let mut output = "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n".to_string();
+    // The ellipsis counts towards max_chars too.
+    let max_chars = max_chars.saturating_sub(1);
+    let mut truncated = String::new();
+    let mut length = 0;
 
-        if let Some(language) = &self.language {
-            output += &format!(
-                "<feed xmlns=\"http://www.w3.org/2005/Atom\" xml:lang=\"{}\">\n",
-                language
-            );
-        } else {
-            output += "<feed xmlns=\"http://www.w3.org/2005/Atom\">\n";
-        };
+    for word in text.split_whitespace() {
+        let word_length = word.chars().count();
+        let separator = if length > 0 { 1 } else { 0 };
 
-        let mut author_exists = false;
-        if let Some(authors) = &self.authors {
-            for author in authors {
-                output += &author.to_atom();
-                author_exists = true;
+        if length + separator + word_length > max_chars {
+            if length == 0 {
+                truncated.extend(word.chars().take(max_chars));
             }
+
+            truncated.push('…');
+            return truncated;
         }
 
-        if !author_exists {
-            output += "<author><name></name></author>\n";
+        if separator > 0 {
+            truncated.push(' ');
         }
 
-        output += &format!("<title>{}</title>\n", self.title);
+        truncated.push_str(word);
+        length += separator + word_length;
+    }
 
-        if let Some(feed_url) = &self.feed_url {
-            output += &format!("<id>{}</id>\n", &feed_url);
-        } else {
-            output += &format!("<id>{}</id>\n", &self.title);
-        }
+    truncated
+}
+
+fn format_duration(seconds: u64) -> String {
+    format!(
+        "{:02}:{:02}:{:02}",
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
 
-        if let Some(home_page_url) = &self.home_page_url {
-            output += &format!("<link rel=\"alternate\" href=\"{}\"/>\n", home_page_url);
+fn tag_host(feed: &Feed) -> Option<String> {
+    let url = feed.home_page_url.as_deref().or(feed.feed_url.as_deref())?;
+    Some(Url::parse(url).ok()?.host_str()?.to_string())
+}
+
+// The date belongs to the item rather than to the feed around it, which
+// changes as items come and go, so an entry keeps its id across regenerations.
+fn item_tag(feed: &Feed, item: &Item, options: &Options) -> Option<String> {
+    let date = match &options.tag_date {
+        Some(tag_date) => tag_date.to_string(),
+        None => {
+            let published = item.date_published.as_deref().and_then(parse_date)?;
+            let date = published.date();
+            format!(
+                "{}-{:02}-{:02}",
+                date.year(),
+                u8::from(date.month()),
+                date.day()
+            )
         }
+    };
+
+    let id: String = form_urlencoded::byte_serialize(item.id.as_bytes()).collect();
+    Some(format!("tag:{},{}:{}", tag_host(feed)?, date, id))
+}
+
+fn is_tag_date(date: &str) -> bool {
+    let parts: Vec<&str> = date.split('-').collect();
+
+    parts.len() <= 3
+        && parts
+            .iter()
+            .zip([4, 2, 2])
+            .all(|(part, len)| part.len() == len && part.bytes().all(|byte| byte.is_ascii_digit()))
+}
+
+fn alternate_type(options: &Options) -> &'static str {
+    if options.link_types {
+        " type=\"text/html\""
+    } else {
+        ""
+    }
+}
+
+fn stylesheet_pi(options: &Options) -> String {
+    match &options.stylesheet {
+        Some(stylesheet) => format!(
+            "<?xml-stylesheet type=\"text/xsl\" href=\"{}\"?>\n",
+            escape_xml(stylesheet)
+        ),
+        None => String::new(),
+    }
+}
+
+fn rights_element(rights: &str, html: bool) -> String {
+    let rights_type = if html { " type=\"html\"" } else { "" };
+    format!("<rights{}>{}</rights>\n", rights_type, escape_xml(rights))
+}
+
+// Both stay the same when the title changes or items come and go.
+fn default_feed_id(feed: &Feed) -> Option<&str> {
+    feed.feed_url.as_deref().or(feed.home_page_url.as_deref())
+}
+
+fn slugify(text: &str) -> String {
+    let mut slug = String::new();
 
-        if let Some(feed_url) = &self.feed_url {
-            output += &format!("<link rel=\"self\" href=\"{}\"/>\n", feed_url);
+    for c in text.chars().flat_map(char::to_lowercase) {
+        if c.is_alphanumeric() {
+            slug.push(c);
+        } else if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
         }
+    }
+
+    slug.trim_end_matches('-').to_string()
+}
+
+fn feed_slug(feed: &Feed) -> Option<String> {
+    let from_url = || {
+        let url = Url::parse(feed.feed_url.as_deref()?).ok()?;
+        Some(slugify(&format!("{}{}", url.host_str()?, url.path())))
+    };
+
+    Some(slugify(&feed.title))
+        .filter(|slug| !slug.is_empty())
+        .or_else(from_url)
+        .filter(|slug| !slug.is_empty())
+}
+
+fn extensions(object: &Value) -> impl Iterator<Item = (&str, &Value)> {
+    object
+        .as_object()
+        .into_iter()
+        .flatten()
+        .filter_map(|(key, value)| {
+            let name = key.strip_prefix('_').filter(|name| is_ncname(name))?;
+            Some((name, value))
+        })
+}
 
-        if let Some(description) = &self.description {
-            output += &format!("<subtitle>{}</subtitle>\n", description);
+fn extension_to_xml(name: &str, value: &Value) -> String {
+    match value {
+        Value::Array(values) => values
+            .iter()
+            .map(|value| extension_to_xml(name, value))
+            .collect(),
+        Value::Object(fields) => {
+            let mut output = format!("<ext:{}>\n", name);
+            for (key, value) in fields.iter().filter(|(key, _)| is_ncname(key)) {
+                output += &extension_to_xml(key, value);
+            }
+            output += &format!("</ext:{}>\n", name);
+            output
         }
+        Value::Null => format!("<ext:{}/>\n", name),
+        Value::String(text) => format!("<ext:{0}>{1}</ext:{0}>\n", name, escape_xml(text)),
+        value => format!("<ext:{0}>{1}</ext:{0}>\n", name, value),
+    }
+}
 
-        if let Some(icon) = &self.icon {
-            output += &format!("<logo>{}</logo>\n", icon);
+// Looked up once per entry, so large feeds need a map rather than a search.
+fn raw_items(raw: &[Value]) -> BTreeMap<&str, &Value> {
+    let mut items = BTreeMap::new();
+
+    for item in raw
+        .iter()
+        .filter_map(|feed| feed.get("items")?.as_array())
+        .flatten()
+    {
+        if let Some(id) = item.get("id").and_then(Value::as_str) {
+            items.entry(id).or_insert(item);
         }
+    }
 
-        if let Some(updated) = self.updated() {
-            output += &format!("<updated>{}</updated>\n", updated);
-        } else {
-            output += &format!("<updated>{}</updated>\n", now());
+    items
+}
+
+trait ToAtom {
+    fn to_atom(&self, context: &Context) -> String;
+
+    fn updated(&self) -> Option<OffsetDateTime> {
+        None
+    }
+
+    fn duration(&self) -> Option<u64> {
+        None
+    }
+}
+
+impl ToAtom for Author {
+    fn to_atom(&self, _context: &Context) -> String {
+        let mut output = "<author>\n<name>".to_string();
+        if let Some(name) = &self.name {
+            output += &escape_xml(name);
         }
+        output += "</name>\n";
 
-        if let Some(items) = &self.items {
-            for item in items {
-                output += &item.to_atom();
+        if let Some(url) = &self.url {
+            if let Some(email) = url.strip_prefix("mailto:") {
+                output += &format!("<email>{}</email>\n", escape_xml(email));
+            } else {
+                output += &format!("<uri>{}</uri>\n", escape_xml(url));
             }
         }
 
-        output += "</feed>";
+        if let Some(avatar) = &self.avatar {
+            output += &format!(
+                "<gd:image rel=\"http://schemas.google.com/g/2005#thumbnail\" src=\"{}\"/>\n",
+                escape_xml(avatar)
+            );
+        }
+        output += "</author>\n";
+
         output
     }
 }
 
-fn main() {
-    let args: Vec<String> = env::args().collect();
-
-    let mut input = None;
-    let mut output = None;
-
-    if args.len() > 1 {
-        if args[1] == "--help" || args[1] == "-h" {
-            let mut help = format!("{} {}\n", PROGRAM, VERSION).to_string();
-            help += "Converts a JSON Feed to Atom. ";
-            help += "Learn about JSON Feed: https://jsonfeed.org/\n\n";
-            help += &format!("Usage:\n    {} [[input] output]\n\n", PROGRAM);
-            help += "input is a path to a JSON Feed file.\n";
-            help += "output is a path to an Atom file (use - to write to stdout).\n\n";
-            help += "-h, --help     show this help and exit\n";
-            help += "    --version  show version information and exit\n";
-            help +=
-                "-f, --force    rewrite file even if modification time is newer than the feed\n";
-            println!("{}", help);
-            process::exit(0);
-        } else if args[1] == "--version" {
-            println!("{} {}", PROGRAM, VERSION);
-            process::exit(0);
-        } else {
-            output = Some(args[1].to_string());
+impl ToAtom for Item {
+    fn updated(&self) -> Option<OffsetDateTime> {
+        if let Some(date_modified) = self.date_modified.as_deref().and_then(parse_date) {
+            return Some(date_modified);
         }
-    } else if args.len() > 2 {
-        input = Some(args[1].to_string());
-        output = Some(args[2].to_string());
+
+        self.date_published.as_deref().and_then(parse_date)
     }
 
-    if let Some(ref d) = output {
-        if d == "-" {
-            output = None;
-        }
+    fn duration(&self) -> Option<u64> {
+        self.attachments
+            .iter()
+            .flatten()
+            .find_map(|attachment| attachment.duration_in_seconds)
     }
 
-    let data = if let Some(input) = input {
-        fs::read_to_string(input).unwrap()
-    } else {
-        eprintln!("Reading from stdin...");
-        let lines = io::stdin().lock().lines();
-        let mut stdin_data = String::new();
+    fn to_atom(&self, context: &Context) -> String {
+        let mut output = "".to_string();
 
-        for line in lines.map_while(Result::ok) {
-            if line.is_empty() {
-                break;
-            }
+        // Entries inherit xml:lang from <feed>, so only repeat it when it differs.
+        let feed_language = context.feed.and_then(|feed| feed.language.as_ref());
+        let language = self
+            .language
+            .as_ref()
+            .filter(|language| Some(*language) != feed_language);
 
-            if !stdin_data.is_empty() {
-                stdin_data.push('\n');
-            }
+        // With --lang-on-text, the text constructs are tagged instead of the entry.
+        let lang = match language.filter(|_| context.options.lang_on_text) {
+            Some(language) => format!(" xml:lang=\"{}\"", escape_xml(language)),
+            None => String::new(),
+        };
+
+        if let Some(language) = language.filter(|_| !context.options.lang_on_text) {
+            output += &format!("<entry xml:lang=\"{}\">\n", escape_xml(language));
+        } else {
+            output += "<entry>\n";
+        };
+
+        let tag = context
+            .feed
+            .filter(|_| context.options.tag_item_ids && Url::parse(&self.id).is_err())
+            .and_then(|feed| item_tag(feed, self, context.options));
 
-            stdin_data.push_str(&line);
+        if let Some(tag) = tag {
+            output += &format!("<id>{}</id>\n", escape_xml(&tag));
+        } else {
+            output += &format!("<id>{}</id>\n", escape_xml(&self.id));
+        }
+        if let Some(title) = &self.title {
+            output += &format!("<title{}>{}</title>\n", lang, escape_xml(title));
         }
 
-        stdin_data
-    };
+        if let Some(url) = &self.url {
+            // An empty --item-link-rel omits rel, which Atom reads as alternate.
+            let rel = match context.options.item_link_rel.as_str() {
+                "" => String::new(),
+                rel => format!(" rel=\"{}\"", escape_xml(rel)),
+            };
 
-    if let Ok(feed) = Feed::parse(&data) {
-        let feed_atom = feed.to_atom();
+            output += &format!(
+                "<link{}{} href=\"{}\"/>\n",
+                rel,
+                alternate_type(context.options),
+                escape_xml(url)
+            );
+        }
 
-        let updated = if let Some(updated) = feed.updated() {
-            updated
-        } else {
-            OffsetDateTime::now_utc()
-        };
+        if let Some(external_url) = &self.external_url {
+            output += &format!(
+                "<link rel=\"related\" href=\"{}\"/>\n",
+                escape_xml(external_url)
+            );
+        }
 
-        if let Some(output) = output {
-            let write_file = if let Some(mtime) = get_mtime(&output) {
-                updated > mtime
+        let content_html = self.content_html.as_deref().map(|content_html| {
+            let content_html = if context.options.sanitize_html {
+                Cow::Owned(ammonia::clean(content_html))
             } else {
-                true
+                Cow::Borrowed(content_html)
             };
 
+            wrap_content(content_html, context.options)
+        });
+
+        if let Some(summary) = &self.summary {
+            output += &format!("<summary{}>{}</summary>\n", lang, escape_xml(summary));
+        } else if let Some(max_chars) = context.options.autosummary {
+            let text = if let Some(content_text) = &self.content_text {
+                content_text.to_string()
+            } else if let Some(content_html) = &content_html {
+                strip_tags(content_html)
+            } else {
+                String::new()
+            };
+
+            let summary = truncate_words(&text, max_chars);
+            if !summary.is_empty() {
+                output += &format!("<summary{}>{}</summary>\n", lang, escape_xml(&summary));
+            }
+        }
+
+        let content_text = self
+            .content_text
+            .as_ref()
+            .filter(|_| !(context.options.prefer_html && self.content_html.is_some()));
+
+        if let Some(content_text) = content_text {
+            let space = if context.options.preserve_whitespace {
+                " xml:space=\"preserve\""
+            } else {
+                ""
+            };
+
+            output += &format!(
+                "<content type=\"text\"{}{}>{}</content>\n",
+                lang,
+                space,
+                escape_xml(content_text)
+            );
+        } else if let Some(content_html) = &content_html {
+            output += &match context.options.content_mode {
+                ContentMode::Cdata => format!(
+                    "<content type=\"html\"{}><![CDATA[{}]]></content>\n",
+                    lang,
+                    escape_cdata(content_html)
+                ),
+                ContentMode::Escaped => format!(
+                    "<content type=\"html\"{}>{}</content>\n",
+                    lang,
+                    escape_xml(content_html)
+                ),
+                ContentMode::Xhtml => match to_xhtml(content_html) {
+                    Some(xhtml) => format!("<content type=\"xhtml\"{}>{}</content>\n", lang, xhtml),
+                    None => format!(
+                        "<content type=\"html\"{}>{}</content>\n",
+                        lang,
+                        escape_xml(content_html)
+                    ),
+                },
+            };
+        }
+
+        if self.summary.is_none() && self.content_text.is_none() && content_html.is_none() {
+            // Readers may drop entries without any text, such as bare bookmarks.
+            if let Some(text) = self.title.as_ref().or(self.url.as_ref()) {
+                output += &format!("<summary{}>{}</summary>\n", lang, escape_xml(text));
+            }
+        }
+
+        let updated = format_date(self.updated().unwrap_or(context.options.now));
+        output += &format!("<updated>{}</updated>\n", updated);
+
+        let mut published = self.date_published.as_deref().and_then(parse_date);
+        if published.is_none() && context.options.published_from_modified {
+            published = self.date_modified.as_deref().and_then(parse_date);
+        }
+
+        if let Some(date_published) = published {
+            output += &format!("<published>{}</published>\n", format_date(date_published));
+        }
+
+        if let Some(rights) = context
+            .options
+            .rights
+            .as_ref()
+            .filter(|_| context.options.rights_per_entry)
+        {
+            output += &rights_element(rights, context.options.rights_html);
+        }
+
+        let mut authors = self.authors.as_ref().filter(|authors| !authors.is_empty());
+        if authors.is_none() && context.options.inherit_authors {
+            authors = context.feed.and_then(|feed| feed.authors.as_ref());
+        }
+
+        for author in authors.into_iter().flatten() {
+            output += &author.to_atom(context);
+        }
+
+        if let Some(image) = &self.image {
+            output += &format!("<media:thumbnail url=\"{}\"/>\n", escape_xml(image));
+        }
+
+        if let Some(banner_image) = &self.banner_image {
+            output += &format!(
+                "<media:content url=\"{}\" medium=\"image\">\n",
+                escape_xml(banner_image)
+            );
+            output += "<media:category scheme=\"https://jsonfeed.org/version/1.1\">";
+            output += "banner_image</media:category>\n";
+            output += "</media:content>\n";
+        }
+
+        if let Some(tags) = &self.tags {
+            for tag in tags {
+                if !tag.is_empty() {
+                    output += &format!("<category term=\"{}\"/>\n", escape_xml(tag));
+                }
+            }
+        }
+
+        if let Some(attachments) = &self.attachments {
+            for attachment in attachments {
+                output += &format!(
+                    "<link rel=\"enclosure\" href=\"{}\" type=\"{}\"",
+                    escape_xml(&attachment.url),
+                    escape_xml(&attachment.mime_type)
+                );
+
+                if let Some(title) = &attachment.title {
+                    output += &format!(" title=\"{}\"", escape_xml(title));
+                }
+
+                if let Some(size_in_bytes) = &attachment.size_in_bytes {
+                    output += &format!(" length=\"{}\"", &size_in_bytes);
+                }
+
+                output += "/>\n";
+            }
+        }
+
+        if let Some(duration) = self.duration() {
+            output += &format!(
+                "<itunes:duration>{}</itunes:duration>\n",
+                format_duration(duration)
+            );
+        }
+
+        if let Some(content_html) = content_html.filter(|_| context.options.content_encoded) {
+            output += &format!(
+                "<content:encoded><![CDATA[{}]]></content:encoded>\n",
+                escape_cdata(&content_html)
+            );
+        }
+
+        if let Some(source) = context.sources.get(&self.id).and_then(Option::as_ref) {
+            output += "<source>\n";
+            if let Some(id) = &source.id {
+                output += &format!("<id>{}</id>\n", escape_xml(id));
+            }
+            output += &format!("<title>{}</title>\n", escape_xml(&source.title));
+            if let Some(home_page_url) = &source.home_page_url {
+                output += &format!(
+                    "<link rel=\"alternate\"{} href=\"{}\"/>\n",
+                    alternate_type(context.options),
+                    escape_xml(home_page_url)
+                );
+            }
+            output += "</source>\n";
+        }
+
+        if let Some(raw) = context.raw_items.get(self.id.as_str()) {
+            for (name, value) in extensions(raw) {
+                output += &extension_to_xml(name, value);
+            }
+        }
+
+        output += "</entry>\n";
+        output
+    }
+}
+
+impl ToAtom for Feed {
+    fn updated(&self) -> Option<OffsetDateTime> {
+        let mut updated = None;
+
+        if let Some(items) = &self.items {
+            for item in items {
+                if let Some(item_updated) = item.updated() {
+                    if let Some(u) = updated {
+                        if item_updated > u {
+                            updated = Some(item_updated);
+                        }
+                    } else {
+                        updated = Some(item_updated);
+                    }
+                }
+            }
+        }
+
+        updated
+    }
+
+    fn to_atom(&self, context: &Context) -> String {
+        let head = atom_head(self, context);
+        let items = self.items.as_ref().map_or(0, Vec::len);
+
+        // A rough guess at the size of an entry saves most reallocations on big feeds.
+        let mut output = String::with_capacity(head.len() + items * ITEM_CAPACITY + 8);
+        output += &head;
+
+        let context = Context {
+            feed: Some(self),
+            ..*context
+        };
+
+        if let Some(items) = &self.items {
+            for item in items_to_atom(items, &context) {
+                output += &item;
+            }
+        }
+
+        output += "</feed>";
+        output
+    }
+}
+
+fn atom_head(feed: &Feed, context: &Context) -> String {
+    let mut output = "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n".to_string();
+    output += &stylesheet_pi(context.options);
+
+    if let Some(user_comment) = &feed.user_comment {
+        let mut comment: String = user_comment.chars().filter(|c| is_xml_char(*c)).collect();
+        while comment.contains("--") {
+            comment = comment.replace("--", "-");
+        }
+
+        output += &format!("<!-- {} -->\n", comment);
+    }
+
+    output += "<feed xmlns=\"http://www.w3.org/2005/Atom\"";
+
+    let mut namespaces = Vec::new();
+
+    if feed.expired == Some(true) {
+        namespaces.push(("fh", "http://purl.org/syndication/history/1.0"));
+    }
+
+    let has_avatar = |authors: &Option<Vec<Author>>| {
+        authors
+            .iter()
+            .flatten()
+            .any(|author| author.avatar.is_some())
+    };
+
+    if has_avatar(&feed.authors)
+        || feed
+            .items
+            .iter()
+            .flatten()
+            .any(|item| has_avatar(&item.authors))
+    {
+        namespaces.push(("gd", "http://schemas.google.com/g/2005"));
+    }
+
+    if feed
+        .items
+        .iter()
+        .flatten()
+        .any(|item| item.image.is_some() || item.banner_image.is_some())
+    {
+        namespaces.push(("media", "http://search.yahoo.com/mrss/"));
+    }
+
+    if feed
+        .items
+        .iter()
+        .flatten()
+        .any(|item| item.duration().is_some())
+    {
+        namespaces.push(("itunes", "http://www.itunes.com/dtds/podcast-1.0.dtd"));
+    }
+
+    if context.options.content_encoded
+        && feed
+            .items
+            .iter()
+            .flatten()
+            .any(|item| item.content_html.is_some())
+    {
+        namespaces.push(("content", "http://purl.org/rss/1.0/modules/content/"));
+    }
+
+    let has_extensions = context.raw.iter().any(|feed| {
+        extensions(feed).next().is_some()
+            || feed
+                .get("items")
+                .and_then(Value::as_array)
+                .into_iter()
+                .flatten()
+                .any(|item| extensions(item).next().is_some())
+    });
+
+    if has_extensions {
+        namespaces.push(("ext", EXTENSIONS_NAMESPACE));
+    }
+
+    for (prefix, uri) in &context.options.namespaces {
+        namespaces.retain(|(declared, _)| declared != prefix);
+        namespaces.push((prefix.as_str(), uri.as_str()));
+    }
+
+    for (prefix, uri) in namespaces {
+        output += &format!(" xmlns:{}=\"{}\"", prefix, escape_xml(uri));
+    }
+
+    if let Some(base) = &context.options.base {
+        output += &format!(" xml:base=\"{}\"", escape_xml(base));
+    }
+
+    if let Some(language) = &feed.language {
+        output += &format!(" xml:lang=\"{}\"", escape_xml(language));
+    }
+
+    output += ">\n";
+
+    let mut author_exists = false;
+    if let Some(authors) = &feed.authors {
+        for author in authors {
+            output += &author.to_atom(context);
+            author_exists = true;
+        }
+    }
+
+    if !author_exists {
+        let name = if let Some(default_author) = &context.options.default_author {
+            default_author.as_str()
+        } else if context.options.title_as_author {
+            feed.title.as_str()
+        } else {
+            ""
+        };
+
+        output += &format!("<author><name>{}</name></author>\n", escape_xml(name));
+    }
+
+    output += &format!("<title>{}</title>\n", escape_xml(&feed.title));
+
+    // prepare() has made sure that there is one.
+    let id = context
+        .options
+        .feed_id
+        .as_deref()
+        .or(default_feed_id(feed))
+        .unwrap_or_default();
+
+    output += &format!("<id>{}</id>\n", escape_xml(id));
+
+    if let Some(home_page_url) = &feed.home_page_url {
+        output += &format!(
+            "<link rel=\"alternate\"{} href=\"{}\"/>\n",
+            alternate_type(context.options),
+            escape_xml(home_page_url)
+        );
+    }
+
+    if let Some(self_url) = context.options.self_url.as_ref().or(feed.feed_url.as_ref()) {
+        output += &format!(
+            "<link rel=\"self\" type=\"application/atom+xml\" href=\"{}\"/>\n",
+            escape_xml(self_url)
+        );
+    }
+
+    if let Some(first_url) = &context.options.first_url {
+        output += &format!("<link rel=\"first\" href=\"{}\"/>\n", escape_xml(first_url));
+    }
+
+    if let Some(prev_url) = &context.options.prev_url {
+        output += &format!(
+            "<link rel=\"previous\" href=\"{}\"/>\n",
+            escape_xml(prev_url)
+        );
+    }
+
+    if let Some(next_url) = &feed.next_url {
+        output += &format!("<link rel=\"next\" href=\"{}\"/>\n", escape_xml(next_url));
+    }
+
+    if let Some(hubs) = &feed.hubs {
+        for hub in hubs {
+            if hub.hub_type.eq_ignore_ascii_case("websub") {
+                output += &format!("<link rel=\"hub\" href=\"{}\"/>\n", escape_xml(&hub.url));
+            }
+        }
+    }
+
+    if let Some(description) = &feed.description {
+        output += &format!("<subtitle>{}</subtitle>\n", escape_xml(description));
+    }
+
+    if let Some(rights) = &context.options.rights {
+        output += &rights_element(rights, context.options.rights_html);
+    }
+
+    if let Some(icon) = &feed.icon {
+        output += &format!("<logo>{}</logo>\n", escape_xml(icon));
+    }
+
+    if let Some(favicon) = &feed.favicon {
+        output += &format!("<icon>{}</icon>\n", escape_xml(favicon));
+    }
+
+    let updated = format_date(feed.updated().unwrap_or(context.options.now));
+    output += &format!("<updated>{}</updated>\n", updated);
+
+    if context.options.generator {
+        output += &format!(
+            "<generator uri=\"{}\" version=\"{}\">{}</generator>\n",
+            REPOSITORY, VERSION, PROGRAM
+        );
+    }
+
+    // An expired JSON Feed will not receive new items, which is what
+    // RFC 5005 calls a complete feed.
+    if feed.expired == Some(true) {
+        output += "<fh:complete/>\n";
+    }
+
+    if let Some(raw) = context.raw.first() {
+        for (name, value) in extensions(raw) {
+            output += &extension_to_xml(name, value);
+        }
+    }
+
+    output
+}
+
+trait ToJson {
+    fn to_json(&self) -> Value;
+}
+
+// JSON Feed leaves out fields that have no value instead of setting them to null.
+fn json_object(fields: Vec<(&str, Value)>) -> Value {
+    Value::Object(
+        fields
+            .into_iter()
+            .filter(|(_, value)| !value.is_null())
+            .map(|(key, value)| (key.to_string(), value))
+            .collect(),
+    )
+}
+
+fn json_date(date: &Option<String>) -> Value {
+    json!(date
+        .as_deref()
+        .map(|date| parse_date(date).map_or(date.to_string(), format_date)))
+}
+
+impl ToJson for Author {
+    fn to_json(&self) -> Value {
+        json_object(vec![
+            ("name", json!(self.name)),
+            ("url", json!(self.url)),
+            ("avatar", json!(self.avatar)),
+        ])
+    }
+}
+
+impl ToJson for Item {
+    fn to_json(&self) -> Value {
+        let attachments = self.attachments.as_ref().map(|attachments| {
+            attachments
+                .iter()
+                .map(|attachment| {
+                    json_object(vec![
+                        ("url", json!(attachment.url)),
+                        ("mime_type", json!(attachment.mime_type)),
+                        ("title", json!(attachment.title)),
+                        ("size_in_bytes", json!(attachment.size_in_bytes)),
+                        ("duration_in_seconds", json!(attachment.duration_in_seconds)),
+                    ])
+                })
+                .collect::<Vec<_>>()
+        });
+
+        json_object(vec![
+            ("id", json!(self.id)),
+            ("url", json!(self.url)),
+            ("external_url", json!(self.external_url)),
+            ("title", json!(self.title)),
+            ("content_html", json!(self.content_html)),
+            ("content_text", json!(self.content_text)),
+            ("summary", json!(self.summary)),
+            ("image", json!(self.image)),
+            ("banner_image", json!(self.banner_image)),
+            ("date_published", json_date(&self.date_published)),
+            ("date_modified", json_date(&self.date_modified)),
+            (
+                "authors",
+                json!(self
+                    .authors
+                    .as_ref()
+                    .map(|authors| authors.iter().map(ToJson::to_json).collect::<Vec<_>>())),
+            ),
+            ("tags", json!(self.tags)),
+            ("language", json!(self.language)),
+            ("attachments", json!(attachments)),
+        ])
+    }
+}
+
+impl ToJson for Feed {
+    fn to_json(&self) -> Value {
+        let hubs = self.hubs.as_ref().map(|hubs| {
+            hubs.iter()
+                .map(|hub| json!({ "type": hub.hub_type, "url": hub.url }))
+                .collect::<Vec<_>>()
+        });
+
+        json_object(vec![
+            ("version", json!(JSON_FEED_VERSIONS[1])),
+            ("title", json!(self.title)),
+            ("home_page_url", json!(self.home_page_url)),
+            ("feed_url", json!(self.feed_url)),
+            ("description", json!(self.description)),
+            ("user_comment", json!(self.user_comment)),
+            ("next_url", json!(self.next_url)),
+            ("icon", json!(self.icon)),
+            ("favicon", json!(self.favicon)),
+            (
+                "authors",
+                json!(self
+                    .authors
+                    .as_ref()
+                    .map(|authors| authors.iter().map(ToJson::to_json).collect::<Vec<_>>())),
+            ),
+            ("language", json!(self.language)),
+            ("expired", json!(self.expired)),
+            ("hubs", json!(hubs)),
+            (
+                "items",
+                Value::Array(self.items.iter().flatten().map(ToJson::to_json).collect()),
+            ),
+        ])
+    }
+}
+
+trait ToRss {
+    fn to_rss(&self, options: &Options) -> String;
+}
+
+fn format_rss_date(date: OffsetDateTime) -> Option<String> {
+    date.format(&well_known::Rfc2822).ok()
+}
+
+impl ToRss for Item {
+    fn to_rss(&self, options: &Options) -> String {
+        let mut output = "<item>\n".to_string();
+
+        if let Some(title) = &self.title {
+            output += &format!("<title>{}</title>\n", escape_xml(title));
+        }
+
+        if let Some(url) = &self.url {
+            output += &format!("<link>{}</link>\n", escape_xml(url));
+        }
+
+        let content_html = self.content_html.as_deref().map(|content_html| {
+            let content_html = if options.sanitize_html {
+                Cow::Owned(ammonia::clean(content_html))
+            } else {
+                Cow::Borrowed(content_html)
+            };
+
+            wrap_content(content_html, options)
+        });
+
+        if let Some(summary) = &self.summary {
+            output += &format!("<description>{}</description>\n", escape_xml(summary));
+        } else if let Some(content_html) = &content_html {
+            output += &format!(
+                "<description><![CDATA[{}]]></description>\n",
+                escape_cdata(content_html)
+            );
+        } else if let Some(content_text) = &self.content_text {
+            output += &format!("<description>{}</description>\n", escape_xml(content_text));
+        }
+
+        if let Some(content_html) = &content_html {
+            output += &format!(
+                "<content:encoded><![CDATA[{}]]></content:encoded>\n",
+                escape_cdata(content_html)
+            );
+        }
+
+        let permalink = Some(&self.id) == self.url.as_ref();
+        output += &format!(
+            "<guid isPermaLink=\"{}\">{}</guid>\n",
+            permalink,
+            escape_xml(&self.id)
+        );
+
+        let published = self.date_published.as_deref().and_then(parse_date);
+        if let Some(date) = published
+            .or_else(|| self.updated())
+            .and_then(format_rss_date)
+        {
+            output += &format!("<pubDate>{}</pubDate>\n", date);
+        }
+
+        if let Some(tags) = &self.tags {
+            for tag in tags {
+                if !tag.is_empty() {
+                    output += &format!("<category>{}</category>\n", escape_xml(tag));
+                }
+            }
+        }
+
+        // RSS allows a single enclosure per item.
+        if let Some(attachment) = self.attachments.iter().flatten().next() {
+            output += &format!(
+                "<enclosure url=\"{}\" length=\"{}\" type=\"{}\"/>\n",
+                escape_xml(&attachment.url),
+                attachment.size_in_bytes.unwrap_or(0),
+                escape_xml(&attachment.mime_type)
+            );
+        }
+
+        output += "</item>\n";
+        output
+    }
+}
+
+impl ToRss for Feed {
+    fn to_rss(&self, options: &Options) -> String {
+        let mut output = "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n".to_string();
+        output += &stylesheet_pi(options);
+
+        output += "<rss version=\"2.0\"";
+        if self
+            .items
+            .iter()
+            .flatten()
+            .any(|item| item.content_html.is_some())
+        {
+            output += " xmlns:content=\"http://purl.org/rss/1.0/modules/content/\"";
+        }
+        output += ">\n<channel>\n";
+
+        output += &format!("<title>{}</title>\n", escape_xml(&self.title));
+
+        if let Some(url) = self.home_page_url.as_ref().or(self.feed_url.as_ref()) {
+            output += &format!("<link>{}</link>\n", escape_xml(url));
+        }
+
+        // Unlike Atom's subtitle, an RSS channel must have a description.
+        let description = self.description.as_ref().unwrap_or(&self.title);
+        output += &format!("<description>{}</description>\n", escape_xml(description));
+
+        if let Some(language) = &self.language {
+            output += &format!("<language>{}</language>\n", escape_xml(language));
+        }
+
+        if let Some(rights) = &options.rights {
+            output += &format!("<copyright>{}</copyright>\n", escape_xml(rights));
+        }
+
+        let updated = self.updated().unwrap_or(options.now);
+        if let Some(date) = format_rss_date(updated) {
+            output += &format!("<lastBuildDate>{}</lastBuildDate>\n", date);
+        }
+
+        if options.generator {
+            output += &format!("<generator>{} {}</generator>\n", PROGRAM, VERSION);
+        }
+
+        if let Some(items) = &self.items {
+            for item in items {
+                output += &item.to_rss(options);
+            }
+        }
+
+        output += "</channel>\n</rss>";
+        output
+    }
+}
+
+fn agent() -> ureq::Agent {
+    ureq::AgentBuilder::new()
+        .timeout(Duration::from_secs(30))
+        .user_agent(&format!("{}/{}", PROGRAM, VERSION))
+        .build()
+}
+
+fn fetch(url: &str, validators: Option<&str>) -> io::Result<Option<(Vec<u8>, String)>> {
+    let mut request = agent().get(url);
+    for line in validators.unwrap_or_default().lines() {
+        if let Some((name, value)) = line.split_once(": ") {
+            if let Some((_, condition)) = CACHE_HEADERS.iter().find(|(header, _)| *header == name) {
+                request = request.set(condition, value);
+            }
+        }
+    }
+
+    let response = match request.call() {
+        Ok(response) => response,
+        Err(ureq::Error::Status(code, _)) => {
+            return Err(io::Error::other(format!("HTTP status {}", code)));
+        }
+        Err(e) => return Err(io::Error::other(e.to_string())),
+    };
+
+    if response.status() == 304 {
+        return Ok(None);
+    }
+
+    if response.status() != 200 {
+        return Err(io::Error::other(format!(
+            "HTTP status {}",
+            response.status()
+        )));
+    }
+
+    let mut cache = String::new();
+    for (header, _) in CACHE_HEADERS {
+        if let Some(value) = response.header(header) {
+            cache += &format!("{}: {}\n", header, value);
+        }
+    }
+
+    let mut bytes = Vec::new();
+    response.into_reader().read_to_end(&mut bytes)?;
+    Ok(Some((bytes, cache)))
+}
+
+fn probe_length(agent: &ureq::Agent, url: &str) -> Option<u64> {
+    if !url.starts_with("http://") && !url.starts_with("https://") {
+        return None;
+    }
+
+    let response = agent.head(url).call().ok()?;
+    response.header("Content-Length")?.parse().ok()
+}
+
+fn decode_input(bytes: Vec<u8>, options: &Options) -> io::Result<String> {
+    let bytes = if options.gzip || bytes.starts_with(&[0x1f, 0x8b]) {
+        let mut data = Vec::new();
+        GzDecoder::new(bytes.as_slice()).read_to_end(&mut data)?;
+        data
+    } else {
+        bytes
+    };
+    let bytes = bytes.strip_prefix(b"\xef\xbb\xbf").unwrap_or(&bytes);
+
+    if let Some(encoding) = options.encoding {
+        let (data, malformed) = encoding.decode_without_bom_handling(bytes);
+
+        if malformed {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("input is not valid {}", encoding.name()),
+            ));
+        }
+
+        return Ok(data.into_owned());
+    }
+
+    match std::str::from_utf8(bytes) {
+        Ok(data) => Ok(data.to_string()),
+        Err(_) => {
+            if !options.quiet {
+                eprintln!("warning: input is not UTF-8, decoding it as windows-1252");
+            }
+            let (data, _) = encoding_rs::WINDOWS_1252.decode_without_bom_handling(bytes);
+            Ok(data.into_owned())
+        }
+    }
+}
+
+// Blanks out comments and trailing commas. Everything else stays where it
+// was, so parse errors still point at the right line and column.
+fn relax_json(data: &str) -> String {
+    let mut chars: Vec<char> = data.chars().collect();
+    let mut i = 0;
+    // Where the last comma outside a string was, if only whitespace followed.
+    let mut comma = None;
+
+    while i < chars.len() {
+        match chars[i] {
+            '"' => {
+                comma = None;
+                i += 1;
+
+                while i < chars.len() && chars[i] != '"' {
+                    i += if chars[i] == '\\' { 2 } else { 1 };
+                }
+            }
+            '/' if chars.get(i + 1) == Some(&'/') => {
+                while i < chars.len() && chars[i] != '\n' {
+                    chars[i] = ' ';
+                    i += 1;
+                }
+                continue;
+            }
+            '/' if chars.get(i + 1) == Some(&'*') => {
+                let end = (i + 2..chars.len().saturating_sub(1))
+                    .find(|&j| chars[j] == '*' && chars[j + 1] == '/')
+                    .map_or(chars.len(), |j| j + 2);
+
+                for c in &mut chars[i..end] {
+                    if *c != '\n' {
+                        *c = ' ';
+                    }
+                }
+                i = end;
+                continue;
+            }
+            ',' => comma = Some(i),
+            '}' | ']' => {
+                if let Some(comma) = comma.take() {
+                    chars[comma] = ' ';
+                }
+            }
+            c if c.is_whitespace() => {}
+            _ => comma = None,
+        }
+
+        i += 1;
+    }
+
+    chars.into_iter().collect()
+}
+
+enum Document<'a> {
+    Buffered(String),
+    // Written entry by entry, so the output is never held in memory as a whole.
+    // The input and the parsed feed still are.
+    Streamed(&'a Feed, Context<'a>),
+}
+
+impl Document<'_> {
+    fn write_to(&self, out: &mut dyn Write) -> io::Result<()> {
+        match self {
+            Document::Buffered(document) => out.write_all(document.as_bytes()),
+            Document::Streamed(feed, context) => write_atom(feed, context, out),
+        }
+    }
+}
+
+fn write_atom(feed: &Feed, context: &Context, out: &mut dyn Write) -> io::Result<()> {
+    let options = context.options;
+    out.write_all(line_endings(atom_head(feed, context), options).as_bytes())?;
+
+    let context = Context {
+        feed: Some(feed),
+        ..*context
+    };
+
+    // Chunks keep the threads busy without rendering the whole feed up front.
+    for chunk in feed.items.as_deref().unwrap_or_default().chunks(ITEM_CHUNK) {
+        for item in items_to_atom(chunk, &context) {
+            out.write_all(line_endings(item, options).as_bytes())?;
+        }
+    }
+
+    out.write_all(finish_document("</feed>".to_string(), options).as_bytes())
+}
+
+fn items_to_atom(items: &[Item], context: &Context) -> Vec<String> {
+    if context.options.jobs > 1 {
+        // Collecting a parallel iterator keeps the entries in feed order.
+        items.par_iter().map(|item| item.to_atom(context)).collect()
+    } else {
+        items.iter().map(|item| item.to_atom(context)).collect()
+    }
+}
+
+fn write_output(document: &Document, compress: bool, out: &mut dyn Write) -> io::Result<()> {
+    if compress {
+        let mut encoder = GzEncoder::new(out, Compression::default());
+        document.write_to(&mut encoder)?;
+        encoder.finish()?;
+        Ok(())
+    } else {
+        document.write_to(out)
+    }
+}
+
+fn read_output(path: &str, compressed: bool) -> io::Result<String> {
+    let bytes = fs::read(path)?;
+
+    if compressed {
+        let mut data = String::new();
+        GzDecoder::new(bytes.as_slice()).read_to_string(&mut data)?;
+        Ok(data)
+    } else {
+        String::from_utf8(bytes).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+}
+
+fn write_atomically(
+    path: &str,
+    write: impl FnOnce(&mut dyn Write) -> io::Result<()>,
+) -> io::Result<()> {
+    // A symlinked output keeps its link, and the file it points to is replaced instead.
+    let path = fs::canonicalize(path).unwrap_or_else(|_| PathBuf::from(path));
+    let permissions = fs::metadata(&path)
+        .map(|metadata| metadata.permissions())
+        .ok();
+    let file_name = path
+        .file_name()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "not a file path"))?;
+
+    let mut temp_name = OsString::from(".");
+    temp_name.push(file_name);
+    temp_name.push(format!(".{}.tmp", process::id()));
+    let temp_path = path.with_file_name(temp_name);
+
+    let result = File::create(&temp_path)
+        .and_then(|file| {
+            let mut writer = BufWriter::new(file);
+            write(&mut writer)?;
+            let file = writer
+                .into_inner()
+                .map_err(io::IntoInnerError::into_error)?;
+
+            if let Some(permissions) = permissions {
+                file.set_permissions(permissions)?;
+            }
+
+            file.sync_all()
+        })
+        .and_then(|_| fs::rename(&temp_path, &path));
+
+    if result.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+
+    result
+}
+
+enum Error {
+    Read(String, io::Error),
+    Write(String, io::Error),
+    Parse(serde_json::Error),
+    Invalid(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Read(path, e) => write!(f, "cannot read {}: {}", path, e),
+            Error::Write(path, e) => write!(f, "cannot write {}: {}", path, e),
+            Error::Parse(e) => write!(f, "cannot parse feed: {}", e),
+            Error::Invalid(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+struct Input {
+    data: String,
+    validators: Option<String>,
+}
+
+// Reads to EOF however the data arrives, so FIFOs, pipes and other inputs
+// without a known size are read whole.
+fn read_all(mut reader: impl Read, options: &Options) -> io::Result<String> {
+    let mut bytes = Vec::new();
+    reader.read_to_end(&mut bytes)?;
+    decode_input(bytes, options)
+}
+
+fn read_input(
+    input: Option<&str>,
+    validators: Option<&str>,
+    options: &Options,
+) -> Result<Option<Input>, Error> {
+    if let Some(input) = input {
+        let (data, validators) = if input.starts_with("http://") || input.starts_with("https://") {
+            match fetch(input, validators) {
+                Ok(Some((bytes, validators))) => (decode_input(bytes, options), Some(validators)),
+                Ok(None) => return Ok(None),
+                Err(e) => (Err(e), None),
+            }
+        } else {
+            (
+                File::open(input).and_then(|file| read_all(file, options)),
+                None,
+            )
+        };
+
+        let data = data.map_err(|e| Error::Read(input.to_string(), e))?;
+
+        Ok(Some(Input { data, validators }))
+    } else {
+        if !options.quiet {
+            eprintln!("Reading from stdin...");
+        }
+
+        let data = read_all(io::stdin().lock(), options)
+            .map_err(|e| Error::Read("stdin".to_string(), e))?;
+
+        Ok(Some(Input {
+            data,
+            validators: None,
+        }))
+    }
+}
+
+fn sort_items(items: &mut [Item], order: SortOrder) {
+    items.sort_by(|a, b| match (a.updated(), b.updated()) {
+        (Some(a), Some(b)) => match order {
+            SortOrder::Ascending => a.cmp(&b),
+            SortOrder::Descending => b.cmp(&a),
+        },
+        (Some(_), None) => Ordering::Less,
+        (None, Some(_)) => Ordering::Greater,
+        (None, None) => Ordering::Equal,
+    });
+}
+
+fn upgrade_author(object: &mut Value) -> bool {
+    if let Some(object) = object.as_object_mut() {
+        if !object.contains_key("authors") {
+            if let Some(author) = object.get("author").filter(|author| author.is_object()) {
+                let authors = Value::Array(vec![author.clone()]);
+                object.insert("authors".to_string(), authors);
+                return true;
+            }
+        }
+    }
+
+    false
+}
+
+fn parse_feed(data: &str, options: &Options) -> Result<Feed, serde_json::Error> {
+    // JSON Feed 1.0 had a single author object where 1.1 has a list.
+    let mut upgraded = false;
+    let mut value: Value = serde_json::from_str(data)?;
+
+    upgraded |= upgrade_author(&mut value);
+    if let Some(items) = value.get_mut("items").and_then(Value::as_array_mut) {
+        for item in items {
+            upgraded |= upgrade_author(item);
+        }
+    }
+
+    let mut feed = if upgraded {
+        // Errors are reported against the original input so the position is useful.
+        Feed::parse(&value.to_string()).or_else(|_| Feed::parse(data))?
+    } else {
+        Feed::parse(data)?
+    };
+
+    normalize_dates(&mut feed, options);
+    Ok(feed)
+}
+
+fn normalize_dates(feed: &mut Feed, options: &Options) {
+    for item in feed.items.iter_mut().flatten() {
+        for date in [&mut item.date_published, &mut item.date_modified]
+            .into_iter()
+            .flatten()
+        {
+            if parse_date(date).is_none() {
+                if let Some(parsed) = parse_loose_date(date) {
+                    if !options.quiet {
+                        eprintln!("warning: item {} has a non-RFC 3339 date {}", item.id, date);
+                    }
+                    *date = format_date(parsed);
+                }
+            }
+        }
+    }
+}
+
+fn merge_feeds(
+    data: &str,
+    options: &Options,
+) -> Result<(Feed, BTreeMap<String, Option<Source>>), Error> {
+    let mut merged: Option<Feed> = None;
+    let mut items = Vec::new();
+    // Where each item id was first seen; None for items of the first feed.
+    let mut sources = BTreeMap::new();
+
+    for (number, line) in data.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        match parse_feed(line, options) {
+            Ok(mut feed) => {
+                let feed_items = feed.items.take().unwrap_or_default();
+
+                let source = merged
+                    .as_ref()
+                    // Without an id, a feed cannot be told apart from the first one.
+                    .filter(|merged| {
+                        default_feed_id(&feed).is_none_or(|id| default_feed_id(merged) != Some(id))
+                    })
+                    .map(|_| Source {
+                        id: default_feed_id(&feed).map(str::to_string),
+                        title: feed.title.to_string(),
+                        home_page_url: feed.home_page_url.clone(),
+                    });
+
+                for item in &feed_items {
+                    sources
+                        .entry(item.id.to_string())
+                        .or_insert_with(|| source.clone());
+                }
+
+                items.extend(feed_items);
+
+                if merged.is_none() {
+                    merged = Some(feed);
+                }
+            }
+            Err(e) => {
+                if !options.quiet {
+                    eprintln!("warning: cannot parse feed on line {}: {}", number + 1, e);
+                }
+            }
+        }
+    }
+
+    let mut feed = merged.ok_or_else(|| Error::Invalid("cannot parse any feed".to_string()))?;
+    sort_items(&mut items, SortOrder::Descending);
+    feed.items = Some(items);
+
+    Ok((feed, sources))
+}
+
+fn print_json_error(data: &str, line: usize, column: usize) {
+    let text = line
+        .checked_sub(1)
+        .and_then(|index| data.lines().nth(index));
+
+    if let Some(text) = text {
+        let chars: Vec<char> = text
+            .chars()
+            .map(|c| if c == '\t' { ' ' } else { c })
+            .collect();
+        let caret = text
+            .char_indices()
+            .take_while(|(offset, _)| *offset + 1 < column)
+            .count();
+        let start = caret.saturating_sub(40);
+        let end = chars.len().min(caret + 40);
+
+        let snippet: String = chars[start..end].iter().collect();
+        eprintln!("{:>5} | {}", line, snippet);
+        eprintln!("      | {}^", " ".repeat(caret - start));
+    }
+}
+
+fn schema_problems(data: &str) -> Vec<String> {
+    let mut problems = Vec::new();
+
+    // Syntax errors are reported when the feed is parsed.
+    let feed: Value = match serde_json::from_str(data) {
+        Ok(feed) => feed,
+        Err(_) => return problems,
+    };
+
+    for field in ["version", "title", "items"] {
+        if feed.get(field).is_none() {
+            problems.push(format!("feed is missing required field {}", field));
+        }
+    }
+
+    for field in ["home_page_url", "feed_url"] {
+        if feed.get(field).is_none() {
+            problems.push(format!("feed is missing recommended field {}", field));
+        }
+    }
+
+    let items = feed.get("items").and_then(Value::as_array);
+    for (number, item) in items.into_iter().flatten().enumerate() {
+        if item.get("id").is_none() {
+            problems.push(format!("item {} is missing required field id", number + 1));
+        }
+
+        if item.get("content_html").is_none() && item.get("content_text").is_none() {
+            problems.push(format!(
+                "item {} has neither content_html nor content_text",
+                number + 1
+            ));
+        }
+    }
+
+    problems
+}
+
+fn print_summary(feed: &Feed, options: &Options) {
+    let items: Vec<&Item> = feed.items.iter().flatten().collect();
+    let count = |matches: fn(&Item) -> bool| items.iter().filter(|item| matches(item)).count();
+
+    let attachments: usize = items
+        .iter()
+        .map(|item| item.attachments.as_ref().map_or(0, Vec::len))
+        .sum();
+
+    let placeholder = feed
+        .authors
+        .as_ref()
+        .is_none_or(|authors| authors.is_empty())
+        && options.default_author.is_none()
+        && !options.title_as_author;
+
+    eprintln!("items: {}", items.len());
+    eprintln!(
+        "items with summary: {}",
+        count(|item| item.summary.is_some())
+    );
+    eprintln!(
+        "items with date_published: {}",
+        count(|item| item.date_published.is_some())
+    );
+    eprintln!(
+        "items with date_modified: {}",
+        count(|item| item.date_modified.is_some())
+    );
+    eprintln!("attachments: {}", attachments);
+    eprintln!("empty author placeholders: {}", usize::from(placeholder));
+}
+
+fn duplicate_ids(feed: &Feed) -> Vec<(&str, usize)> {
+    let mut counts = BTreeMap::new();
+
+    for item in feed.items.iter().flatten() {
+        *counts.entry(item.id.as_str()).or_insert(0) += 1;
+    }
+
+    counts.into_iter().filter(|(_, count)| *count > 1).collect()
+}
+
+fn prepare(
+    data: &str,
+    options: &Options,
+) -> Result<(Feed, BTreeMap<String, Option<Source>>), Error> {
+    if options.schema_check {
+        let problems: Vec<String> = if options.merge {
+            data.lines().flat_map(schema_problems).collect()
+        } else {
+            schema_problems(data)
+        };
+
+        for problem in problems.iter().filter(|_| !options.quiet) {
+            eprintln!("warning: {}", problem);
+        }
+
+        if options.strict && !problems.is_empty() {
+            return Err(Error::Invalid(
+                "feed does not follow the JSON Feed schema".to_string(),
+            ));
+        }
+    }
+
+    let (mut feed, sources) = if options.merge {
+        merge_feeds(data, options)?
+    } else {
+        match parse_feed(data, options) {
+            Ok(feed) => (feed, BTreeMap::new()),
+            Err(e) => {
+                if options.pretty_json_error {
+                    print_json_error(data, e.line(), e.column());
+                }
+                return Err(Error::Parse(e));
+            }
+        }
+    };
+
+    // A feed without items converts the same as one with an empty list.
+    feed.items.get_or_insert_with(Vec::new);
+
+    if options.collapse_whitespace {
+        feed.title = collapse_whitespace(&feed.title);
+
+        for item in feed.items.iter_mut().flatten() {
+            for text in [&mut item.title, &mut item.summary].into_iter().flatten() {
+                *text = collapse_whitespace(text);
+            }
+        }
+    }
+
+    if let Some(title) = &options.title {
+        feed.title = title.to_string();
+    }
+
+    if options.probe_enclosures {
+        let agent = agent();
+
+        for item in feed.items.iter_mut().flatten() {
+            for attachment in item.attachments.iter_mut().flatten() {
+                if attachment.size_in_bytes.is_none() {
+                    attachment.size_in_bytes = probe_length(&agent, &attachment.url);
+                }
+            }
+        }
+    }
+
+    if feed.title.trim().is_empty() {
+        if !options.quiet {
+            eprintln!("warning: feed has no title");
+        }
+
+        if options.strict {
+            return Err(Error::Invalid("feed has no title".to_string()));
+        }
+
+        // Atom requires a feed title, and the URL is better than nothing.
+        if let Some(url) = feed.home_page_url.as_ref().or(feed.feed_url.as_ref()) {
+            feed.title = url.to_string();
+        }
+    }
+
+    // The title is neither permanent nor an IRI, so it cannot stand in for an id.
+    if options.format == OutputFormat::Atom
+        && options.feed_id.is_none()
+        && default_feed_id(&feed).is_none()
+    {
+        return Err(Error::Invalid(
+            "feed has no feed_url or home_page_url to use as its id, pass --feed-id".to_string(),
+        ));
+    }
+
+    if let (Some(order), Some(items)) = (options.sort, feed.items.as_mut()) {
+        sort_items(items, order);
+    }
+
+    if options.max_age.is_some() || options.drop_undated {
+        if let Some(items) = feed.items.as_mut() {
+            // An age reaching back past the earliest representable date keeps everything.
+            let oldest = options
+                .max_age
+                .and_then(|max_age| options.now.checked_sub(max_age));
+
+            items.retain(|item| match item.updated() {
+                Some(updated) => oldest.is_none_or(|oldest| updated >= oldest),
+                None => !options.drop_undated,
+            });
+        }
+    }
+
+    if options.limit > 0 {
+        if let Some(items) = feed.items.as_mut() {
+            items.truncate(options.limit);
+        }
+    }
+
+    if options.verbose {
+        eprintln!("version: {}", feed.version);
+    }
+
+    if !JSON_FEED_VERSIONS.contains(&feed.version.as_str()) {
+        if !options.quiet {
+            eprintln!("warning: unsupported JSON Feed version {}", feed.version);
+        }
+
+        if options.strict {
+            return Err(Error::Invalid(
+                "feed has an unsupported version".to_string(),
+            ));
+        }
+    }
+
+    if options.verbose {
+        print_summary(&feed, options);
+    }
+
+    if options.tag_item_ids && options.tag_date.is_none() {
+        let undated = feed
+            .items
+            .iter()
+            .flatten()
+            .filter(|item| Url::parse(&item.id).is_err())
+            .filter(|item| {
+                item.date_published
+                    .as_deref()
+                    .and_then(parse_date)
+                    .is_none()
+            })
+            .count();
+
+        if undated > 0 && !options.quiet {
+            eprintln!(
+                "warning: {} item ids need a date_published or --tag-date to become tag URIs",
+                undated
+            );
+        }
+    }
+
+    let duplicates = duplicate_ids(&feed);
+    for (id, count) in duplicates.iter().filter(|_| !options.quiet) {
+        eprintln!("warning: item id {} appears {} times", id, count);
+    }
+
+    if options.strict && !duplicates.is_empty() {
+        return Err(Error::Invalid(
+            "feed contains duplicate item ids".to_string(),
+        ));
+    }
+
+    Ok((feed, sources))
+}
+
+fn raw_feeds(data: &str, options: &Options) -> Vec<Value> {
+    if !options.keep_extensions {
+        Vec::new()
+    } else if options.merge {
+        data.lines()
+            .filter_map(|line| serde_json::from_str(line).ok())
+            .collect()
+    } else {
+        serde_json::from_str(data).into_iter().collect()
+    }
+}
+
+fn serialize(feed: &Feed, context: &Context) -> Result<String, Error> {
+    let options = context.options;
+
+    let document = if options.format == OutputFormat::JsonFeed {
+        feed.to_json().to_string()
+    } else {
+        let mut feed_atom = feed.to_atom(context);
+
+        if options.minify {
+            feed_atom = format_xml(&feed_atom, "", "");
+        } else if options.pretty {
+            feed_atom = format_xml(&feed_atom, "\n", "  ");
+        }
+
+        if options.validate {
+            if let Err(e) = roxmltree::Document::parse(&feed_atom) {
+                let pos = e.pos();
+                return Err(Error::Invalid(format!(
+                    "generated Atom is not well-formed at line {}, column {}: {}",
+                    pos.row, pos.col, e
+                )));
+            }
+        }
+
+        feed_atom
+    };
+
+    Ok(finish_document(document, options))
+}
+
+fn write_pages(data: &str, prefix: &str, options: &Options) -> Result<(), Error> {
+    let (mut feed, sources) = prepare(data, options)?;
+    let raw = raw_feeds(data, options);
+    let raw_items = raw_items(&raw);
+    let updated = feed.updated().unwrap_or(options.now);
+    let mut items = feed.items.take().unwrap_or_default();
+    let pages = items.len().div_ceil(options.page_size).max(1);
+
+    let path = |page: usize| format!("{}-{}.{}", prefix, page, options.format.extension());
+    // Pages link to each other relative to where they are stored.
+    let href = |page: usize| {
+        Path::new(&path(page))
+            .file_name()
+            .map(|file_name| file_name.to_string_lossy().into_owned())
+            .unwrap_or_default()
+    };
+
+    // Every page is part of the same feed, so they share one id.
+    let mut page_options = options.clone();
+    page_options.feed_id = options
+        .feed_id
+        .as_deref()
+        .or(default_feed_id(&feed))
+        .map(str::to_string);
+    page_options.first_url = Some(href(1));
+
+    // The feed keeps its own URLs, so tag URIs for items are the same on every page.
+    for page in 1..=pages {
+        let rest = items.split_off(options.page_size.min(items.len()));
+        feed.items = Some(items);
+        feed.next_url = (page < pages).then(|| href(page + 1));
+        page_options.self_url = Some(href(page));
+        page_options.prev_url = (page > 1).then(|| href(page - 1));
+
+        let context = Context {
+            options: &page_options,
+            feed: None,
+            raw: &raw,
+            raw_items: &raw_items,
+            sources: &sources,
+        };
+        let document = serialize(&feed, &context)?;
+        let output = path(page);
+        let write_page = options.force || is_stale(&output, updated, options);
+
+        if options.dry_run {
+            let status = if write_page {
+                "would write"
+            } else {
+                "up to date"
+            };
+            eprintln!("{}: {}", output, status);
+        } else if write_page {
+            let document = Document::Buffered(document);
+
+            write_atomically(&output, |out| {
+                write_output(&document, options.gzip_output, out)
+            })
+            .map_err(|e| Error::Write(output.to_string(), e))?;
+        }
+
+        items = rest;
+    }
+
+    Ok(())
+}
+
+fn finish_document(mut document: String, options: &Options) -> String {
+    if options.trailing_newline {
+        document.push('\n');
+    }
+
+    line_endings(document, options)
+}
+
+fn line_endings(document: String, options: &Options) -> String {
+    if options.crlf {
+        // XML parsers read CRLF in text as LF, so the content does not change.
+        document.replace("\r\n", "\n").replace('\n', "\r\n")
+    } else {
+        document
+    }
+}
+
+fn render_rss(feed: &Feed, options: &Options) -> String {
+    let mut feed_rss = feed.to_rss(options);
+
+    if options.minify {
+        feed_rss = format_xml(&feed_rss, "", "");
+    } else if options.pretty {
+        feed_rss = format_xml(&feed_rss, "\n", "  ");
+    }
+
+    finish_document(feed_rss, options)
+}
+
+// Converts a JSON Feed without touching any files, for testing the output.
+#[cfg(test)]
+fn convert_str(data: &str, options: &Options) -> Result<String, Error> {
+    let (feed, sources) = prepare(data, options)?;
+    let raw = raw_feeds(data, options);
+    let raw_items = raw_items(&raw);
+    let context = Context {
+        options,
+        feed: None,
+        raw: &raw,
+        raw_items: &raw_items,
+        sources: &sources,
+    };
+    serialize(&feed, &context)
+}
+
+fn convert(input: Option<&str>, output: Option<&str>, options: &Options) -> Result<(), Error> {
+    let cache = output
+        .filter(|output| options.http_cache && !Path::new(output).is_dir())
+        .map(|output| format!("{}.http-cache", output));
+    // A 304 only helps if the files written last time are still there.
+    let outputs_exist = output.is_some_and(|output| Path::new(output).exists())
+        && options
+            .also_rss
+            .as_deref()
+            .is_none_or(|rss_output| Path::new(rss_output).exists());
+    let validators = cache
+        .as_deref()
+        .filter(|_| !options.force && outputs_exist)
+        .and_then(|cache| fs::read_to_string(cache).ok());
+
+    let input_data = match read_input(input, validators.as_deref(), options)? {
+        Some(input_data) => input_data,
+        None => {
+            if !options.quiet {
+                eprintln!("{}: not modified", input.unwrap_or_default());
+            }
+            return Ok(());
+        }
+    };
+    let data = if options.lenient_json {
+        relax_json(&input_data.data)
+    } else {
+        input_data.data
+    };
+
+    if let Some(prefix) = &options.page_prefix {
+        return write_pages(&data, prefix, options);
+    }
+
+    let (feed, sources) = prepare(&data, options)?;
+    let raw = raw_feeds(&data, options);
+    let raw_items = raw_items(&raw);
+    let updated = feed.updated().unwrap_or(options.now);
+
+    // These need the whole document before anything can be written.
+    let buffered = options.format != OutputFormat::Atom
+        || options.pretty
+        || options.minify
+        || options.validate
+        || options.skip_unchanged;
+
+    let context = Context {
+        options,
+        feed: None,
+        raw: &raw,
+        raw_items: &raw_items,
+        sources: &sources,
+    };
+
+    let document = if buffered {
+        Document::Buffered(serialize(&feed, &context)?)
+    } else {
+        Document::Streamed(&feed, context)
+    };
+
+    if let Some(output) = output {
+        let output = if Path::new(output).is_dir() {
+            let slug = feed_slug(&feed).ok_or_else(|| {
+                Error::Invalid(format!(
+                    "cannot name a file in {}: feed has no usable title or feed_url",
+                    output
+                ))
+            })?;
+
+            Path::new(output)
+                .join(format!("{}.{}", slug, options.format.extension()))
+                .to_string_lossy()
+                .into_owned()
+        } else {
+            output.to_string()
+        };
+        let output = output.as_str();
+
+        let compress = options.gzip_output || output.ends_with(".gz");
+
+        // A FIFO or device has no useful mtime and must not be replaced by a rename.
+        let special = fs::metadata(output).is_ok_and(|metadata| !metadata.is_file());
+
+        let unchanged = match &document {
+            Document::Buffered(document) if options.skip_unchanged && !special => {
+                read_output(output, compress).is_ok_and(|existing| {
+                    same_except_now(&existing, document, &format_date(options.now))
+                })
+            }
+            _ => false,
+        };
+
+        let write_file =
+            (options.force || special || is_stale(output, updated, options)) && !unchanged;
+
+        if options.dry_run {
+            let status = if write_file {
+                "would write"
+            } else {
+                "up to date"
+            };
+            eprintln!("{}: {}", output, status);
+        } else {
             if write_file {
-                let mut output = File::create(output).unwrap();
-                writeln!(output, "{}", feed_atom).unwrap();
+                if special {
+                    File::create(output).and_then(|file| {
+                        let mut writer = BufWriter::new(file);
+                        write_output(&document, compress, &mut writer)?;
+                        writer.flush()
+                    })
+                } else {
+                    write_atomically(output, |out| write_output(&document, compress, out))
+                }
+                .map_err(|e| Error::Write(output.to_string(), e))?;
+            }
+
+            if let (Some(cache), Some(validators)) = (&cache, &input_data.validators) {
+                fs::write(cache, validators).map_err(|e| Error::Write(cache.to_string(), e))?;
+            }
+        }
+    } else if options.dry_run {
+        eprintln!("stdout: would write");
+    } else {
+        let mut writer = BufWriter::new(io::stdout().lock());
+
+        write_output(&document, options.gzip_output, &mut writer)
+            .and_then(|_| writer.flush())
+            .map_err(|e| Error::Write("stdout".to_string(), e))?;
+    }
+
+    if let Some(rss_output) = &options.also_rss {
+        let write_rss = options.force || is_stale(rss_output, updated, options);
+
+        if options.dry_run {
+            let status = if write_rss {
+                "would write"
+            } else {
+                "up to date"
+            };
+            eprintln!("{}: {}", rss_output, status);
+        } else if write_rss {
+            let compress = options.gzip_output || rss_output.ends_with(".gz");
+
+            let document = Document::Buffered(render_rss(&feed, options));
+
+            write_atomically(rss_output, |out| write_output(&document, compress, out))
+                .map_err(|e| Error::Write(rss_output.to_string(), e))?;
+        }
+    }
+
+    Ok(())
+}
+
+fn is_stale(output: &str, updated: OffsetDateTime, options: &Options) -> bool {
+    let reference = match &options.newer_than {
+        Some(reference) => get_mtime(reference),
+        None => Some(updated),
+    };
+
+    match (reference, get_mtime(output)) {
+        (Some(reference), Some(mtime)) => reference > mtime,
+        _ => true,
+    }
+}
+
+fn same_except_now(old: &str, new: &str, now: &str) -> bool {
+    let old_parts: Vec<&str> = old.split("<updated>").collect();
+    let new_parts: Vec<&str> = new.split("<updated>").collect();
+
+    // Dates that were filled in from --now differ on every run, so they don't count.
+    old_parts.len() == new_parts.len()
+        && old_parts
+            .iter()
+            .zip(&new_parts)
+            .enumerate()
+            .all(|(number, (old, new))| {
+                match (old.split_once("</updated>"), new.split_once("</updated>")) {
+                    (Some((old_date, old_rest)), Some((new_date, new_rest))) if number > 0 => {
+                        (old_date == new_date || new_date == now) && old_rest == new_rest
+                    }
+                    _ => old == new,
+                }
+            })
+}
+
+fn batch_output(outdir: &str, input: &str, format: OutputFormat) -> String {
+    let file_name = Path::new(input)
+        .file_name()
+        .map(|file_name| file_name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let file_name = file_name.strip_suffix(".gz").unwrap_or(&file_name);
+
+    let stem = Path::new(file_name)
+        .file_stem()
+        .map_or(Cow::Borrowed("feed"), |stem| stem.to_string_lossy());
+
+    Path::new(outdir)
+        .join(format!("{}.{}", stem, format.extension()))
+        .to_string_lossy()
+        .into_owned()
+}
+
+fn is_ncname(name: &str) -> bool {
+    let mut chars = name.chars();
+    let first = chars.next();
+
+    first.is_some_and(|c| c.is_alphabetic() || c == '_')
+        && chars.all(|c| c.is_alphanumeric() || matches!(c, '_' | '-' | '.'))
+        && !name.to_ascii_lowercase().starts_with("xml")
+}
+
+fn parse_age(age: &str) -> Option<time::Duration> {
+    let unit = age.chars().last()?;
+    let number = i64::from(age[..age.len() - unit.len_utf8()].parse::<u32>().ok()?);
+
+    match unit {
+        's' => Some(time::Duration::seconds(number)),
+        'm' => Some(time::Duration::minutes(number)),
+        'h' => Some(time::Duration::hours(number)),
+        'd' => Some(time::Duration::days(number)),
+        'w' => Some(time::Duration::weeks(number)),
+        _ => None,
+    }
+}
+
+fn parse_epoch(epoch: &str) -> Option<OffsetDateTime> {
+    let now = OffsetDateTime::from_unix_timestamp(epoch.parse().ok()?).ok()?;
+    Some(now).filter(|now| has_rfc3339_year(*now))
+}
+
+fn flag_value(args: &mut impl Iterator<Item = String>, flag: &str) -> String {
+    if let Some(value) = args.next() {
+        value
+    } else {
+        eprintln!("error: {} requires a value", flag);
+        process::exit(1);
+    }
+}
+
+fn main() {
+    let mut positional = Vec::new();
+    let mut options = Options::default();
+
+    if let Ok(epoch) = env::var("SOURCE_DATE_EPOCH") {
+        match parse_epoch(&epoch) {
+            Some(now) => options.now = now,
+            None => {
+                eprintln!(
+                    "error: SOURCE_DATE_EPOCH must be a Unix timestamp in the years 0 to 9999"
+                );
+                process::exit(1);
+            }
+        }
+    }
+
+    let mut args = env::args().skip(1);
+
+    while let Some(arg) = args.next() {
+        if arg == "--help" || arg == "-h" {
+            let mut help = format!("{} {}\n", PROGRAM, VERSION).to_string();
+            help += "Converts a JSON Feed to Atom. ";
+            help += "Learn about JSON Feed: https://jsonfeed.org/\n\n";
+            help += &format!("Usage:\n    {} [input [output]]\n", PROGRAM);
+            help += &format!("    {} --outdir <dir> input...\n\n", PROGRAM);
+            help += "input is a path or http(s) URL to a JSON Feed (- or omitted: stdin).\n";
+            help += "output is a path to an Atom file or a directory to create it in\n";
+            help += "(- or omitted: stdout).\n\n";
+
+            let width = FLAGS.iter().map(|(flag, _)| flag.len()).max().unwrap_or(0) + 2;
+            for (flag, description) in FLAGS {
+                help += &format!("{:<width$}{}\n", flag, description, width = width);
+            }
+            println!("{}", help);
+            process::exit(0);
+        } else if arg == "--version" {
+            println!("{} {}", PROGRAM, VERSION);
+            process::exit(0);
+        } else if arg == "--stdout" {
+            options.stdout = true;
+        } else if arg == "--format" {
+            options.format = match flag_value(&mut args, &arg).as_str() {
+                "atom" => OutputFormat::Atom,
+                "jsonfeed" => OutputFormat::JsonFeed,
+                _ => {
+                    eprintln!("error: --format must be atom or jsonfeed");
+                    process::exit(1);
+                }
+            };
+        } else if arg == "--dry-run" || arg == "-n" {
+            options.dry_run = true;
+        } else if arg == "--force" || arg == "-f" {
+            options.force = true;
+        } else if arg == "--pretty" {
+            options.pretty = true;
+        } else if arg == "--minify" {
+            options.minify = true;
+        } else if arg == "--no-trailing-newline" {
+            options.trailing_newline = false;
+        } else if arg == "--eol" {
+            options.crlf = match flag_value(&mut args, &arg).as_str() {
+                "crlf" => true,
+                "lf" => false,
+                _ => {
+                    eprintln!("error: --eol must be lf or crlf");
+                    process::exit(1);
+                }
+            };
+        } else if arg == "--gzip" {
+            options.gzip = true;
+        } else if arg == "--validate" {
+            options.validate = true;
+        } else if arg == "--strict" {
+            options.strict = true;
+        } else if arg == "--lenient-json" {
+            options.lenient_json = true;
+        } else if arg == "--schema-check" {
+            options.schema_check = true;
+        } else if arg == "--pretty-json-error" {
+            options.pretty_json_error = true;
+        } else if arg == "--quiet" || arg == "-q" {
+            options.quiet = true;
+        } else if arg == "--verbose" || arg == "-v" {
+            options.verbose = true;
+        } else if arg == "--merge" {
+            options.merge = true;
+        } else if arg == "--sort" {
+            options.sort = match flag_value(&mut args, &arg).as_str() {
+                "asc" => Some(SortOrder::Ascending),
+                "desc" => Some(SortOrder::Descending),
+                _ => {
+                    eprintln!("error: --sort must be asc or desc");
+                    process::exit(1);
+                }
+            };
+        } else if arg == "--max-age" {
+            match parse_age(&flag_value(&mut args, &arg)) {
+                Some(max_age) => options.max_age = Some(max_age),
+                None => {
+                    eprintln!("error: --max-age must be a number followed by s, m, h, d or w");
+                    process::exit(1);
+                }
+            }
+        } else if arg == "--drop-undated" {
+            options.drop_undated = true;
+        } else if arg == "--limit" {
+            match flag_value(&mut args, &arg).parse() {
+                Ok(limit) => options.limit = limit,
+                Err(_) => {
+                    eprintln!("error: --limit must be a number");
+                    process::exit(1);
+                }
+            }
+        } else if arg == "--gzip-output" {
+            options.gzip_output = true;
+        } else if arg == "--http-cache" {
+            options.http_cache = true;
+        } else if arg == "--probe-enclosures" {
+            options.probe_enclosures = true;
+        } else if arg == "--encoding" {
+            let label = flag_value(&mut args, &arg);
+
+            match encoding_rs::Encoding::for_label(label.as_bytes()) {
+                Some(encoding) => options.encoding = Some(encoding),
+                None => {
+                    eprintln!("error: unknown encoding {}", label);
+                    process::exit(1);
+                }
+            }
+        } else if arg == "--newer-than" {
+            let reference = flag_value(&mut args, &arg);
+
+            if let Err(e) = fs::metadata(&reference) {
+                eprintln!("error: cannot read {}: {}", reference, e);
+                process::exit(1);
+            }
+
+            options.newer_than = Some(reference);
+        } else if arg == "--skip-unchanged" {
+            options.skip_unchanged = true;
+        } else if arg == "--also-rss" {
+            options.also_rss = Some(flag_value(&mut args, &arg));
+        } else if arg == "--jobs" {
+            match flag_value(&mut args, &arg).parse() {
+                Ok(jobs) if jobs > 0 => options.jobs = jobs,
+                _ => {
+                    eprintln!("error: --jobs must be a positive number");
+                    process::exit(1);
+                }
+            }
+        } else if arg == "--page-size" {
+            match flag_value(&mut args, &arg).parse() {
+                Ok(page_size) if page_size > 0 => options.page_size = page_size,
+                _ => {
+                    eprintln!("error: --page-size must be a positive number");
+                    process::exit(1);
+                }
+            }
+        } else if arg == "--page-prefix" {
+            options.page_prefix = Some(flag_value(&mut args, &arg));
+        } else if arg == "--outdir" {
+            options.outdir = Some(flag_value(&mut args, &arg));
+        } else if arg == "--title" {
+            options.title = Some(flag_value(&mut args, &arg));
+        } else if arg == "--stylesheet" {
+            options.stylesheet = Some(flag_value(&mut args, &arg));
+        } else if arg == "--no-generator" {
+            options.generator = false;
+        } else if arg == "--inherit-authors" {
+            options.inherit_authors = true;
+        } else if arg == "--default-author" {
+            options.default_author = Some(flag_value(&mut args, &arg));
+        } else if arg == "--title-as-author" {
+            options.title_as_author = true;
+        } else if arg == "--prefer" {
+            options.prefer_html = match flag_value(&mut args, &arg).as_str() {
+                "html" => true,
+                "text" => false,
+                _ => {
+                    eprintln!("error: --prefer must be text or html");
+                    process::exit(1);
+                }
+            };
+        } else if arg == "--autosummary" {
+            match flag_value(&mut args, &arg).parse() {
+                Ok(max_chars) if max_chars > 0 => options.autosummary = Some(max_chars),
+                _ => {
+                    eprintln!("error: --autosummary must be a positive number");
+                    process::exit(1);
+                }
+            }
+        } else if arg == "--sanitize-html" {
+            options.sanitize_html = true;
+        } else if arg == "--content-wrapper" {
+            let template = flag_value(&mut args, &arg);
+
+            if template.matches(CONTENT_PLACEHOLDER).count() != 1 {
+                eprintln!(
+                    "error: --content-wrapper must contain {} exactly once",
+                    CONTENT_PLACEHOLDER
+                );
+                process::exit(1);
+            }
+
+            // The template must balance on its own for the xhtml content mode.
+            if to_xhtml(&template.replace(CONTENT_PLACEHOLDER, "")).is_none() {
+                eprintln!("error: --content-wrapper is not well-formed markup");
+                process::exit(1);
+            }
+
+            options.content_wrapper = Some(template);
+        } else if arg == "--content-mode" {
+            options.content_mode = match flag_value(&mut args, &arg).as_str() {
+                "cdata" => ContentMode::Cdata,
+                "escaped" => ContentMode::Escaped,
+                "xhtml" => ContentMode::Xhtml,
+                _ => {
+                    eprintln!("error: --content-mode must be cdata, escaped or xhtml");
+                    process::exit(1);
+                }
+            };
+        } else if arg == "--content-encoded" {
+            options.content_encoded = true;
+        } else if arg == "--lang-on-text" {
+            options.lang_on_text = true;
+        } else if arg == "--keep-extensions" {
+            options.keep_extensions = true;
+        } else if arg == "--collapse-whitespace" {
+            options.collapse_whitespace = true;
+        } else if arg == "--preserve-whitespace" {
+            options.preserve_whitespace = true;
+        } else if arg == "--item-link-rel" {
+            options.item_link_rel = flag_value(&mut args, &arg);
+        } else if arg == "--no-link-types" {
+            options.link_types = false;
+        } else if arg == "--rights" {
+            options.rights = Some(flag_value(&mut args, &arg));
+        } else if arg == "--rights-type" {
+            options.rights_html = match flag_value(&mut args, &arg).as_str() {
+                "html" => true,
+                "text" => false,
+                _ => {
+                    eprintln!("error: --rights-type must be text or html");
+                    process::exit(1);
+                }
+            };
+        } else if arg == "--rights-per-entry" {
+            options.rights_per_entry = true;
+        } else if arg == "--first-url" {
+            options.first_url = Some(flag_value(&mut args, &arg));
+        } else if arg == "--prev-url" {
+            options.prev_url = Some(flag_value(&mut args, &arg));
+        } else if arg == "--ns" {
+            let namespace = flag_value(&mut args, &arg);
+
+            match namespace.split_once('=') {
+                Some((prefix, uri)) if is_ncname(prefix) && !uri.is_empty() => {
+                    options
+                        .namespaces
+                        .push((prefix.to_string(), uri.to_string()));
+                }
+                _ => {
+                    eprintln!("error: --ns must be prefix=uri with an XML name as prefix");
+                    process::exit(1);
+                }
+            }
+        } else if arg == "--base" {
+            let base = flag_value(&mut args, &arg);
+
+            if let Err(e) = Url::parse(&base) {
+                eprintln!("error: --base must be an absolute URL: {}", e);
+                process::exit(1);
+            }
+
+            options.base = Some(base);
+        } else if arg == "--feed-id" {
+            let feed_id = flag_value(&mut args, &arg);
+
+            if let Err(e) = Url::parse(&feed_id) {
+                eprintln!("error: --feed-id must be an absolute IRI: {}", e);
+                process::exit(1);
+            }
+
+            options.feed_id = Some(feed_id);
+        } else if arg == "--tag-item-ids" {
+            options.tag_item_ids = true;
+        } else if arg == "--tag-date" {
+            let tag_date = flag_value(&mut args, &arg);
+
+            if !is_tag_date(&tag_date) {
+                eprintln!("error: --tag-date must be YYYY, YYYY-MM or YYYY-MM-DD");
+                process::exit(1);
+            }
+
+            options.tag_date = Some(tag_date);
+        } else if arg == "--published-from-modified" {
+            options.published_from_modified = true;
+        } else if arg == "--now" {
+            match parse_date(&flag_value(&mut args, &arg)) {
+                Some(now) => options.now = now,
+                None => {
+                    eprintln!("error: --now must be an RFC 3339 date");
+                    process::exit(1);
+                }
             }
+        } else if arg.starts_with('-') && arg != "-" {
+            eprintln!("error: unknown option {} (see --help)", arg);
+            process::exit(1);
         } else {
-            println!("{}", feed_atom);
+            positional.push(arg);
         }
-    } else {
-        eprintln!("Cannot parse feed.");
+    }
+
+    if options.rights_per_entry && options.rights.is_none() {
+        eprintln!("error: --rights-per-entry requires --rights");
+        process::exit(1);
+    }
+
+    if (options.page_size > 0) != options.page_prefix.is_some() {
+        eprintln!("error: --page-size and --page-prefix must be used together");
+        process::exit(1);
+    }
+
+    if options.jobs > 1 {
+        if let Err(e) = rayon::ThreadPoolBuilder::new()
+            .num_threads(options.jobs)
+            .build_global()
+        {
+            eprintln!("error: cannot start {} threads: {}", options.jobs, e);
+            process::exit(1);
+        }
+    }
+
+    if options.page_prefix.is_some() {
+        if options.also_rss.is_some() {
+            eprintln!("error: --also-rss cannot be combined with --page-prefix");
+            process::exit(1);
+        }
+
+        if options.http_cache {
+            eprintln!("error: --http-cache cannot be combined with --page-prefix");
+            process::exit(1);
+        }
+
+        if options.skip_unchanged {
+            eprintln!("error: --skip-unchanged cannot be combined with --page-prefix");
+            process::exit(1);
+        }
+    }
+
+    if let Some(outdir) = &options.outdir {
+        if options.page_prefix.is_some() {
+            eprintln!("error: --page-prefix cannot be combined with --outdir");
+            process::exit(1);
+        }
+
+        if options.also_rss.is_some() {
+            eprintln!("error: --also-rss cannot be combined with --outdir");
+            process::exit(1);
+        }
+
+        if positional.is_empty() {
+            eprintln!("error: --outdir requires at least one input");
+            process::exit(1);
+        }
+
+        if !options.dry_run {
+            if let Err(e) = fs::create_dir_all(outdir) {
+                eprintln!("error: cannot create {}: {}", outdir, e);
+                process::exit(1);
+            }
+        }
+
+        let mut failures = 0;
+
+        for input in &positional {
+            let output = batch_output(outdir, input, options.format);
+
+            if let Err(e) = convert(Some(input.as_str()), Some(output.as_str()), &options) {
+                eprintln!("error: {}", e);
+                failures += 1;
+            }
+        }
+
+        if !options.quiet {
+            eprintln!(
+                "{} converted, {} failed",
+                positional.len() - failures,
+                failures
+            );
+        }
+
+        if failures > 0 {
+            process::exit(1);
+        }
+
+        return;
+    }
+
+    let (input, output) = match positional.len() {
+        0 => (None, None),
+        1 => (Some(positional[0].as_str()), None),
+        2 => (Some(positional[0].as_str()), Some(positional[1].as_str())),
+        _ => {
+            eprintln!("Too many arguments.");
+            process::exit(1);
+        }
+    };
+
+    if options.page_prefix.is_some() && output.is_some() {
+        eprintln!("error: --page-prefix cannot be combined with an output path");
+        process::exit(1);
+    }
+
+    if options.stdout && output.is_some() {
+        eprintln!("error: --stdout cannot be combined with an output path");
+        process::exit(1);
+    }
+
+    let input = input.filter(|input| *input != "-" && *input != "/dev/stdin");
+    let output = output.filter(|output| *output != "-" && *output != "/dev/stdout");
+
+    if let Err(e) = convert(input, output, &options) {
+        eprintln!("error: {}", e);
         process::exit(1);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOW: &str = "2023-11-14T22:13:20Z";
+
+    fn options() -> Options {
+        Options {
+            generator: false,
+            now: parse_date(NOW).unwrap(),
+            ..Default::default()
+        }
+    }
+
+    fn feed(items: &str) -> String {
+        format!(
+            r#"{{
+                "version": "https://jsonfeed.org/version/1.1",
+                "title": "Example",
+                "home_page_url": "https://example.org/",
+                "feed_url": "https://example.org/feed.json",
+                "items": [{}]
+            }}"#,
+            items
+        )
+    }
+
+    fn atom(items: &str) -> String {
+        convert_str(&feed(items), &options()).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    // The text of each `name` element directly inside an entry.
+    fn entry_texts(atom: &str, name: &str) -> Vec<String> {
+        let document = roxmltree::Document::parse(atom).unwrap();
+
+        document
+            .descendants()
+            .filter(|node| node.has_tag_name(name))
+            .filter(|node| {
+                node.parent()
+                    .is_some_and(|parent| parent.has_tag_name("entry"))
+            })
+            .map(|node| node.text().unwrap_or_default().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn converts_a_fixture() {
+        let atom = atom(
+            r#"{
+                "id": "1",
+                "url": "https://example.org/1",
+                "title": "One",
+                "content_text": "Hello",
+                "date_published": "2023-01-02T03:04:05Z"
+            }"#,
+        );
+
+        assert_eq!(
+            atom,
+            concat!(
+                "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n",
+                "<feed xmlns=\"http://www.w3.org/2005/Atom\">\n",
+                "<author><name></name></author>\n",
+                "<title>Example</title>\n",
+                "<id>https://example.org/feed.json</id>\n",
+                "<link rel=\"alternate\" type=\"text/html\" href=\"https://example.org/\"/>\n",
+                "<link rel=\"self\" type=\"application/atom+xml\" ",
+                "href=\"https://example.org/feed.json\"/>\n",
+                "<updated>2023-01-02T03:04:05Z</updated>\n",
+                "<entry>\n",
+                "<id>1</id>\n",
+                "<title>One</title>\n",
+                "<link rel=\"alternate\" type=\"text/html\" href=\"https://example.org/1\"/>\n",
+                "<content type=\"text\">Hello</content>\n",
+                "<updated>2023-01-02T03:04:05Z</updated>\n",
+                "<published>2023-01-02T03:04:05Z</published>\n",
+                "</entry>\n",
+                "</feed>\n",
+            )
+        );
+    }
+
+    #[test]
+    fn cdata_content_survives_its_end_marker() {
+        let atom = atom(r#"{"id": "1", "content_html": "<script>x]]></script>"}"#);
+        let document = roxmltree::Document::parse(&atom).unwrap();
+        let content = document
+            .descendants()
+            .find(|node| node.has_tag_name("content"))
+            .unwrap();
+
+        assert_eq!(content.attribute("type"), Some("html"));
+        assert_eq!(content.text(), Some("<script>x]]></script>"));
+    }
+
+    #[test]
+    fn absent_and_empty_items_convert_the_same() {
+        let absent = r#"{
+            "version": "https://jsonfeed.org/version/1.1",
+            "title": "Example",
+            "home_page_url": "https://example.org/",
+            "feed_url": "https://example.org/feed.json"
+        }"#;
+        let options = Options {
+            validate: true,
+            ..options()
+        };
+
+        let empty = convert_str(&feed(""), &options).unwrap_or_else(|e| panic!("{}", e));
+        let absent = convert_str(absent, &options).unwrap_or_else(|e| panic!("{}", e));
+        assert_eq!(empty, absent);
+
+        let document = roxmltree::Document::parse(&empty).unwrap();
+        let feed = document.root_element();
+        assert!(feed.has_tag_name("feed"));
+        assert!(!feed.children().any(|node| node.has_tag_name("entry")));
+
+        let updated = feed
+            .children()
+            .find(|node| node.has_tag_name("updated"))
+            .unwrap();
+        assert_eq!(updated.text(), Some(NOW));
+    }
+
+    const PUBLISHED: &str = "2023-01-02T03:04:05Z";
+    const MODIFIED: &str = "2023-02-03T04:05:06Z";
+
+    fn dates(item: &str) -> (Vec<String>, Vec<String>) {
+        let atom = atom(item);
+        (
+            entry_texts(&atom, "updated"),
+            entry_texts(&atom, "published"),
+        )
+    }
+
+    #[test]
+    fn undated_entry_is_updated_now_and_not_published() {
+        let (updated, published) = dates(r#"{"id": "1", "content_text": "x"}"#);
+
+        assert_eq!(updated, [NOW]);
+        assert!(published.is_empty());
+    }
+
+    #[test]
+    fn published_entry_is_updated_when_published() {
+        let (updated, published) = dates(&format!(
+            r#"{{"id": "1", "content_text": "x", "date_published": "{}"}}"#,
+            PUBLISHED
+        ));
+
+        assert_eq!(updated, [PUBLISHED]);
+        assert_eq!(published, [PUBLISHED]);
+    }
+
+    #[test]
+    fn modified_entry_is_updated_when_modified_and_not_published() {
+        let (updated, published) = dates(&format!(
+            r#"{{"id": "1", "content_text": "x", "date_modified": "{}"}}"#,
+            MODIFIED
+        ));
+
+        assert_eq!(updated, [MODIFIED]);
+        assert!(published.is_empty());
+    }
+
+    #[test]
+    fn published_and_modified_entry_keeps_both_dates() {
+        let (updated, published) = dates(&format!(
+            r#"{{"id": "1", "content_text": "x", "date_published": "{}", "date_modified": "{}"}}"#,
+            PUBLISHED, MODIFIED
+        ));
+
+        assert_eq!(updated, [MODIFIED]);
+        assert_eq!(published, [PUBLISHED]);
+    }
+
+    #[test]
+    fn enclosure_is_self_closed() {
+        let atom = atom(
+            r#"{
+                "id": "1",
+                "content_text": "x",
+                "attachments": [{
+                    "url": "https://example.org/1.mp3",
+                    "mime_type": "audio/mpeg",
+                    "size_in_bytes": 123
+                }]
+            }"#,
+        );
+        let enclosure = atom
+            .lines()
+            .find(|line| line.starts_with("<link rel=\"enclosure\""))
+            .unwrap();
+
+        assert_eq!(
+            enclosure,
+            concat!(
+                "<link rel=\"enclosure\" href=\"https://example.org/1.mp3\" ",
+                "type=\"audio/mpeg\" length=\"123\"/>"
+            )
+        );
+        assert!(!enclosure.contains("\"/ "));
+        assert!(roxmltree::Document::parse(&atom).is_ok());
+    }
+
+    #[test]
+    fn summary_and_text_content_are_escaped() {
+        let atom =
+            atom(r#"{"id": "1", "summary": "a < b && c > d", "content_text": "a < b && c > d"}"#);
+
+        assert!(atom.contains("<summary>a &lt; b &amp;&amp; c &gt; d</summary>\n"));
+        assert!(atom.contains("<content type=\"text\">a &lt; b &amp;&amp; c &gt; d</content>\n"));
+        assert_eq!(entry_texts(&atom, "summary"), ["a < b && c > d"]);
+    }
+
+    #[test]
+    fn tagged_item_ids_do_not_depend_on_other_items() {
+        let old = r#"{"id": "1", "content_text": "x", "date_published": "2019-05-06T07:08:09Z"}"#;
+        let new = r#"{"id": "2", "content_text": "x", "date_published": "2023-01-02T03:04:05Z"}"#;
+        let options = Options {
+            tag_item_ids: true,
+            ..options()
+        };
+        let ids = |items: &str| {
+            let atom = convert_str(&feed(items), &options).unwrap_or_else(|e| panic!("{}", e));
+            entry_texts(&atom, "id")
+        };
+
+        assert_eq!(
+            ids(&format!("{}, {}", new, old)),
+            [
+                "tag:example.org,2023-01-02:2",
+                "tag:example.org,2019-05-06:1"
+            ]
+        );
+        assert_eq!(ids(new), ["tag:example.org,2023-01-02:2"]);
+    }
+
+    #[test]
+    fn feed_id_falls_back_to_the_home_page() {
+        let data = r#"{
+            "version": "https://jsonfeed.org/version/1.1",
+            "title": "Example",
+            "home_page_url": "https://example.org/",
+            "items": []
+        }"#;
+        let atom = convert_str(data, &options()).unwrap_or_else(|e| panic!("{}", e));
+
+        assert!(atom.contains("<id>https://example.org/</id>\n"));
+    }
+
+    #[test]
+    fn feed_without_urls_needs_a_feed_id() {
+        let data = r#"{
+            "version": "https://jsonfeed.org/version/1.1",
+            "title": "Example",
+            "items": []
+        }"#;
+
+        assert!(convert_str(data, &options()).is_err());
+
+        let options = Options {
+            feed_id: Some("urn:example:feed".to_string()),
+            ..options()
+        };
+        let atom = convert_str(data, &options).unwrap_or_else(|e| panic!("{}", e));
+        assert!(atom.contains("<id>urn:example:feed</id>\n"));
+    }
+
+    #[test]
+    fn huge_max_age_keeps_every_item() {
+        let options = Options {
+            max_age: parse_age("5000000d"),
+            ..options()
+        };
+        let data =
+            feed(r#"{"id": "1", "content_text": "x", "date_published": "2000-01-01T00:00:00Z"}"#);
+        let atom = convert_str(&data, &options).unwrap_or_else(|e| panic!("{}", e));
+
+        assert_eq!(entry_texts(&atom, "id"), ["1"]);
+    }
+
+    #[test]
+    fn minify_keeps_whitespace_in_content() {
+        let options = Options {
+            minify: true,
+            ..options()
+        };
+        let data = feed(r#"{"id": "1", "content_text": "\n"}"#);
+        let atom = convert_str(&data, &options).unwrap_or_else(|e| panic!("{}", e));
+
+        assert_eq!(entry_texts(&atom, "content"), ["\n"]);
+    }
+
+    #[test]
+    fn pretty_keeps_whitespace_in_xhtml_content() {
+        let options = Options {
+            pretty: true,
+            content_mode: ContentMode::Xhtml,
+            ..options()
+        };
+        let data = feed(r#"{"id": "1", "content_html": "<pre><b>a</b>\n<i>b</i></pre>"}"#);
+        let atom = convert_str(&data, &options).unwrap_or_else(|e| panic!("{}", e));
+
+        assert!(atom.contains("<pre><b>a</b>\n<i>b</i></pre>"));
+    }
+
+    #[test]
+    fn json_feed_output_is_escaped_and_omits_absent_fields() {
+        let options = Options {
+            format: OutputFormat::JsonFeed,
+            ..options()
+        };
+        let data = feed(r#"{"id": "1", "title": "\"One\"\n", "content_text": "x"}"#);
+        let json = convert_str(&data, &options).unwrap_or_else(|e| panic!("{}", e));
+        let value: Value = serde_json::from_str(&json).unwrap();
+
+        assert!(json.starts_with("{\"version\":"));
+        assert_eq!(value["items"][0]["title"], "\"One\"\n");
+        assert!(value["items"][0].get("url").is_none());
+    }
+
+    #[test]
+    fn extensions_are_kept_per_entry() {
+        let options = Options {
+            keep_extensions: true,
+            ..options()
+        };
+        let data = feed(
+            r#"{"id": "1", "content_text": "x", "_example": {"about": "one"}},
+               {"id": "2", "content_text": "x", "_example": {"about": "two"}}"#,
+        );
+        let atom = convert_str(&data, &options).unwrap_or_else(|e| panic!("{}", e));
+        let document = roxmltree::Document::parse(&atom).unwrap();
+        let about: Vec<&str> = document
+            .descendants()
+            .filter(|node| node.has_tag_name((EXTENSIONS_NAMESPACE, "about")))
+            .filter_map(|node| node.text())
+            .collect();
+
+        assert_eq!(about, ["one", "two"]);
+    }
+
+    // Hands out a few bytes at a time, like a pipe with a slow writer.
+    struct Chunked<'a>(&'a [u8]);
+
+    impl Read for Chunked<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let len = self.0.len().min(buf.len()).min(3);
+            buf[..len].copy_from_slice(&self.0[..len]);
+            self.0 = &self.0[len..];
+            Ok(len)
+        }
+    }
+
+    #[test]
+    fn reads_pipe_like_input_to_the_end() {
+        let data = feed("\n\n{\"id\": \"1\", \"content_text\": \"x\"}\n\n");
+        let read = read_all(Chunked(data.as_bytes()), &options()).unwrap();
+
+        assert_eq!(read, data);
+        assert!(convert_str(&read, &options()).is_ok());
+    }
+
+    #[test]
+    fn source_date_epoch_must_have_a_four_digit_year() {
+        assert_eq!(
+            parse_epoch("1700000000").map(format_date).as_deref(),
+            Some(NOW)
+        );
+        assert!(parse_epoch("-62167219201").is_none());
+        assert!(parse_epoch("253402300800").is_none());
+        assert!(parse_epoch("soon").is_none());
+    }
+
+    #[test]
+    fn loose_dates_need_a_four_digit_year() {
+        assert_eq!(
+            parse_loose_date("2023-01-02").map(format_date).as_deref(),
+            Some("2023-01-02T00:00:00Z")
+        );
+        assert!(parse_loose_date("-0001-01-01").is_none());
+
+        let atom = atom(r#"{"id": "1", "content_text": "x", "date_published": "-0001-01-01"}"#);
+        assert!(entry_texts(&atom, "published").is_empty());
+    }
+
+    #[test]
+    fn strip_tags_decodes_references() {
+        assert_eq!(
+            strip_tags("<p>It&#8217;s&hellip; &mdash; &#x2014; &amp;lt; &bogus; AT&T</p>"),
+            " It’s… — — &lt; &bogus; AT&T "
+        );
+    }
+
+    #[test]
+    fn truncated_words_fit_the_budget_with_the_ellipsis() {
+        assert_eq!(truncate_words("hello world", 11), "hello world");
+        assert_eq!(truncate_words("hello world again", 11), "hello…");
+        assert_eq!(truncate_words("incomprehensible", 5), "inco…");
+        assert_eq!(truncate_words("hello", 1), "…");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn atomic_writes_keep_symlinks_and_permissions() {
+        use std::os::unix::fs::{symlink, PermissionsExt};
+
+        let dir = env::temp_dir().join(format!("json2atom-test-{}", process::id()));
+        let target = dir.join("target.atom");
+        let link = dir.join("link.atom");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(&target, "old").unwrap();
+        fs::set_permissions(&target, fs::Permissions::from_mode(0o640)).unwrap();
+        let _ = fs::remove_file(&link);
+        symlink(&target, &link).unwrap();
+
+        write_atomically(link.to_str().unwrap(), |out| out.write_all(b"new")).unwrap();
+
+        assert!(fs::symlink_metadata(&link)
+            .unwrap()
+            .file_type()
+            .is_symlink());
+        assert_eq!(fs::read_to_string(&target).unwrap(), "new");
+        assert_eq!(
+            fs::metadata(&target).unwrap().permissions().mode() & 0o777,
+            0o640
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn relax_json_drops_a_comma_after_an_escaped_backslash() {
+        let relaxed = relax_json(r#"{"k": "a\\", }"#);
+
+        assert_eq!(relaxed, r#"{"k": "a\\"  }"#);
+        assert!(serde_json::from_str::<Value>(&relaxed).is_ok());
+    }
+
+    #[test]
+    fn relax_json_drops_a_comma_before_a_comment() {
+        assert_eq!(relax_json("[1, // note\n]"), "[1         \n]");
+    }
+
+    #[test]
+    fn relax_json_blanks_an_unterminated_comment() {
+        assert_eq!(relax_json(r#"{"a": 1} /* open"#), r#"{"a": 1}        "#);
+    }
+
+    #[test]
+    fn relax_json_leaves_strings_alone() {
+        let json = r#"{"u": "http://x/*y*/", "v": "a, ]"}"#;
+        assert_eq!(relax_json(json), json);
+    }
+
+    #[test]
+    fn to_xhtml_closes_void_elements() {
+        assert_eq!(
+            to_xhtml(r#"<p>a<br>b<img src="x.png"></p>"#).as_deref(),
+            Some(concat!(
+                r#"<div xmlns="http://www.w3.org/1999/xhtml">"#,
+                r#"<p>a<br/>b<img src="x.png"/></p></div>"#
+            ))
+        );
+    }
+
+    #[test]
+    fn to_xhtml_gives_up_on_unknown_entities() {
+        assert!(to_xhtml("<p>&bogus;</p>").is_none());
+
+        let options = Options {
+            content_mode: ContentMode::Xhtml,
+            ..options()
+        };
+        let data = feed(r#"{"id": "1", "content_html": "<p>&bogus;</p>"}"#);
+        let atom = convert_str(&data, &options).unwrap_or_else(|e| panic!("{}", e));
+        assert!(atom.contains("<content type=\"html\">&lt;p&gt;&amp;bogus;&lt;/p&gt;</content>\n"));
+    }
+
+    #[test]
+    fn same_except_now_ignores_only_filled_in_dates() {
+        let old = "<feed>\n<updated>2020-01-01T00:00:00Z</updated>\n<entry>x</entry>\n</feed>";
+        let now = format!(
+            "<feed>\n<updated>{}</updated>\n<entry>x</entry>\n</feed>",
+            NOW
+        );
+        let dated = "<feed>\n<updated>2021-01-01T00:00:00Z</updated>\n<entry>x</entry>\n</feed>";
+        let changed = format!(
+            "<feed>\n<updated>{}</updated>\n<entry>y</entry>\n</feed>",
+            NOW
+        );
+
+        assert!(same_except_now(old, &now, NOW));
+        assert!(!same_except_now(old, dated, NOW));
+        assert!(!same_except_now(old, &changed, NOW));
+    }
+}